@@ -0,0 +1,156 @@
+// Bitboards, one bit per square in the same order as Board::b, so (x, y) is bit y*8 + x
+use std::sync::OnceLock;
+
+pub const FILE_A: u64 = 0x0101010101010101;
+pub const FILE_H: u64 = FILE_A << 7;
+pub const RANK_1: u64 = 0xff;
+pub const RANK_8: u64 = RANK_1 << 56;
+// a1, c1, ..., b2, d2 and so on
+pub const DARK_SQUARES: u64 = 0xaa55aa55aa55aa55;
+
+// Statics rather than consts so each table is in memory once instead of inlined at every use
+pub static KNIGHT_ATTACKS: [u64; 64] = leaper_table(&[(2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2), (1, 2)]);
+pub static KING_ATTACKS: [u64; 64] = leaper_table(&[(1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1), (1, 1)]);
+// Squares a pawn attacks, white pawns first and black pawns second
+pub static PAWN_ATTACKS: [[u64; 64]; 2] = [leaper_table(&[(-1, 1), (1, 1)]), leaper_table(&[(-1, -1), (1, -1)])];
+
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(0, 1), (-1, 0), (0, -1), (1, 0)];
+
+// Builds the attacks of a piece that jumps by fixed offsets, for every square
+const fn leaper_table(offsets: &[(i32, i32)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            let x = sq as i32 % 8 + offsets[i].0;
+            let y = sq as i32 / 8 + offsets[i].1;
+            if x >= 0 && x < 8 && y >= 0 && y < 8 {
+                table[sq] |= 1 << (y*8 + x);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+// Walks every direction until the edge or the first blocker, the blocker itself is attacked.
+// Slow, only used to fill in the magic tables.
+fn slide(sq: usize, occ: u64, directions: &[(i32, i32)]) -> u64 {
+    let mut attacks = 0;
+    for (i, j) in directions {
+        let (mut x, mut y) = (sq as i32 % 8 + i, sq as i32 / 8 + j);
+        while (0..8).contains(&x) && (0..8).contains(&y) {
+            let bit = 1 << (y*8 + x);
+            attacks |= bit;
+            if occ & bit != 0 {
+                break;
+            }
+            x += i;
+            y += j;
+        }
+    }
+    attacks
+}
+
+// Magic bitboards: the blockers that matter for a square times the magic number give,
+// in their top bits, an index into that square's slice of the attack table
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occ: u64) -> usize {
+        self.offset + ((occ & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct Sliders {
+    bishop: Vec<Magic>,
+    rook: Vec<Magic>,
+    table: Vec<u64>,
+}
+
+static SLIDERS: OnceLock<Sliders> = OnceLock::new();
+
+// The magic numbers get searched for the first time a slider is looked up
+fn sliders() -> &'static Sliders {
+    SLIDERS.get_or_init(|| {
+        let mut table = vec![];
+        let bishop = (0..64).map(|sq| find_magic(sq, &BISHOP_DIRECTIONS, &mut table)).collect();
+        let rook = (0..64).map(|sq| find_magic(sq, &ROOK_DIRECTIONS, &mut table)).collect();
+        Sliders { bishop, rook, table }
+    })
+}
+
+// Tries sparse random numbers until one maps every blocker set without a harmful collision,
+// then appends the square's attacks to the table
+fn find_magic(sq: usize, directions: &[(i32, i32)], table: &mut Vec<u64>) -> Magic {
+    // Pieces on the edge never block anything further along
+    let (x, y) = (sq as u64 % 8, sq as u64 / 8);
+    let edges = ((RANK_1 | RANK_8) & !(RANK_1 << (8*y))) | ((FILE_A | FILE_H) & !(FILE_A << x));
+    let mask = slide(sq, 0, directions) & !edges;
+    let bits = mask.count_ones();
+
+    // Every subset of the mask along with its attacks
+    let mut blockers = vec![];
+    let mut subset: u64 = 0;
+    loop {
+        blockers.push((subset, slide(sq, subset, directions)));
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            break;
+        }
+    }
+
+    // xorshift seeded by rank, these seeds are known to find every magic in a few thousand tries
+    // (they're the ones Stockfish uses), and being fixed every run finds the same magics
+    let mut seed: u64 = [728, 10316, 55013, 32803, 12281, 15100, 16645, 255][sq / 8];
+    let mut random = move || {
+        seed ^= seed >> 12;
+        seed ^= seed << 25;
+        seed ^= seed >> 27;
+        seed.wrapping_mul(0x2545f4914f6cdd1d)
+    };
+
+    let mut attacks = vec![0; 1 << bits];
+    // Which attempt last filled in each entry, so the entries don't need clearing between attempts
+    let mut filled = vec![0; 1 << bits];
+    let mut attempt = 0;
+    loop {
+        let magic = random() & random() & random();
+        if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+            continue;
+        }
+        attempt += 1;
+        let found = blockers.iter().all(|(occ, a)| {
+            let i = (occ.wrapping_mul(magic) >> (64 - bits)) as usize;
+            if filled[i] == attempt && attacks[i] != *a {
+                return false;
+            }
+            filled[i] = attempt;
+            attacks[i] = *a;
+            true
+        });
+        if found {
+            let offset = table.len();
+            table.extend_from_slice(&attacks);
+            return Magic { mask, magic, shift: 64 - bits, offset };
+        }
+    }
+}
+
+pub fn bishop_attacks(sq: usize, occ: u64) -> u64 {
+    let sliders = sliders();
+    sliders.table[sliders.bishop[sq].index(occ)]
+}
+
+pub fn rook_attacks(sq: usize, occ: u64) -> u64 {
+    let sliders = sliders();
+    sliders.table[sliders.rook[sq].index(occ)]
+}
@@ -0,0 +1,379 @@
+// Evaluation in centipawns from white's point of view. Every term is scored twice, once for the
+// middlegame and once for the endgame, and the two get blended by how much material is left.
+use crate::bitboard::{bishop_attacks, rook_attacks, FILE_A, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
+use crate::pawns::{self, ahead};
+use std::fmt;
+
+use crate::{Board, Color, Evaluate, Type};
+
+// Indexed by Type as usize
+const MG_VALUE: [i32; 6] = [82, 337, 365, 477, 1025, 0];
+const EG_VALUE: [i32; 6] = [94, 281, 297, 512, 936, 0];
+
+// How much each piece counts towards the middlegame, a full board adds up to MAX_PHASE
+const PHASE: [i32; 6] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+// Per square a piece can move to beyond the usual number for it, so a typical piece scores about
+// 0. Squares taken by our own pieces or covered by their pawns don't count. Indexed by Type as usize.
+const MOBILITY_MG: [i32; 6] = [0, 4, 5, 2, 1, 0];
+const MOBILITY_EG: [i32; 6] = [0, 4, 5, 4, 2, 0];
+const MOBILITY_BASE: [i32; 6] = [0, 4, 6, 6, 12, 0];
+
+// King danger grows with the pieces aiming at the squares around the king. Each attacked square
+// adds its attacker's weight, and the total looks up the penalty, which rises faster than the
+// weight does since one attacker is seldom a threat but several usually are.
+const ATTACK_WEIGHT: [i32; 6] = [0, 2, 2, 3, 5, 0];
+const MIN_ATTACKERS: i32 = 2;
+const KING_DANGER: [i32; 64] = danger_table();
+// Pawn shield in front of the king, per file beside or on the king's one
+const SHIELD_ADVANCED: i32 = -10;
+const SHIELD_MISSING: i32 = -25;
+// Files next to or on the king's one with no pawn of ours, or no pawns at all
+const HALF_OPEN_FILE: i32 = -10;
+const OPEN_FILE: i32 = -20;
+
+const fn danger_table() -> [i32; 64] {
+    let mut table = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        let danger = (i*i/2) as i32;
+        table[i] = if danger < 500 {danger} else {500};
+        i += 1;
+    }
+    table
+}
+
+// Piece-square tables, the PeSTO ones from https://www.chessprogramming.org/PeSTO%27s_Evaluation_Function.
+// Laid out the way the board looks from white's side, so a8 comes first and h1 last.
+const MG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     98, 134,  61,  95,  68, 126,  34, -11,
+     -6,   7,  26,  31,  65,  56,  25, -20,
+    -14,  13,   6,  21,  23,  12,  17, -23,
+    -27,  -2,  -5,  12,  17,   6,  10, -25,
+    -26,  -4,  -4, -10,   3,   3,  33, -12,
+    -35,  -1, -20, -23, -15,  24,  38, -22,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+const EG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+    178, 173, 158, 134, 147, 132, 165, 187,
+     94, 100,  85,  67,  56,  53,  82,  84,
+     32,  24,  13,   5,  -2,   4,  17,  17,
+     13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+      4,   7,  -6,   1,   0,  -5,  -1,  -8,
+     13,   8,   8,  10,  13,   0,   2,  -7,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+const MG_KNIGHT: [i32; 64] = [
+   -167, -89, -34, -49,  61, -97, -15,-107,
+    -73, -41,  72,  36,  23,  62,   7, -17,
+    -47,  60,  37,  65,  84, 129,  73,  44,
+     -9,  17,  19,  53,  37,  69,  18,  22,
+    -13,   4,  16,  13,  28,  19,  21,  -8,
+    -23,  -9,  12,  10,  19,  17,  25, -16,
+    -29, -53, -12,  -3,  -1,  18, -14, -19,
+   -105, -21, -58, -33, -17, -28, -19, -23,
+];
+const EG_KNIGHT: [i32; 64] = [
+    -58, -38, -13, -28, -31, -27, -63, -99,
+    -25,  -8, -25,  -2,  -9, -25, -24, -52,
+    -24, -20,  10,   9,  -1,  -9, -19, -41,
+    -17,   3,  22,  22,  22,  11,   8, -18,
+    -18,  -6,  16,  25,  16,  17,   4, -18,
+    -23,  -3,  -1,  15,  10,  -3, -20, -22,
+    -42, -20, -10,  -5,  -2, -20, -23, -44,
+    -29, -51, -23, -15, -22, -18, -50, -64,
+];
+const MG_BISHOP: [i32; 64] = [
+    -29,   4, -82, -37, -25, -42,   7,  -8,
+    -26,  16, -18, -13,  30,  59,  18, -47,
+    -16,  37,  43,  40,  35,  50,  37,  -2,
+     -4,   5,  19,  50,  37,  37,   7,  -2,
+     -6,  13,  13,  26,  34,  12,  10,   4,
+      0,  15,  15,  15,  14,  27,  18,  10,
+      4,  15,  16,   0,   7,  21,  33,   1,
+    -33,  -3, -14, -21, -13, -12, -39, -21,
+];
+const EG_BISHOP: [i32; 64] = [
+    -14, -21, -11,  -8,  -7,  -9, -17, -24,
+     -8,  -4,   7, -12,  -3, -13,  -4, -14,
+      2,  -8,   0,  -1,  -2,   6,   0,   4,
+     -3,   9,  12,   9,  14,  10,   3,   2,
+     -6,   3,  13,  19,   7,  10,  -3,  -9,
+    -12,  -3,   8,  10,  13,   3,  -7, -15,
+    -14, -18,  -7,  -1,   4,  -9, -15, -27,
+    -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+];
+const MG_ROOK: [i32; 64] = [
+     32,  42,  32,  51,  63,   9,  31,  43,
+     27,  32,  58,  62,  80,  67,  26,  44,
+     -5,  19,  26,  36,  17,  45,  61,  16,
+    -24, -11,   7,  26,  24,  35,  -8, -20,
+    -36, -26, -12,  -1,   9,  -7,   6, -23,
+    -45, -25, -16, -17,   3,   0,  -5, -33,
+    -44, -16, -20,  -9,  -1,  11,  -6, -71,
+    -19, -13,   1,  17,  16,   7, -37, -26,
+];
+const EG_ROOK: [i32; 64] = [
+     13,  10,  18,  15,  12,  12,   8,   5,
+     11,  13,  13,  11,  -3,   3,   8,   3,
+      7,   7,   7,   5,   4,  -3,  -5,  -3,
+      4,   3,  13,   1,   2,   1,  -1,   2,
+      3,   5,   8,   4,  -5,  -6,  -8, -11,
+     -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+     -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+     -9,   2,   3,  -1,  -5, -13,   4, -20,
+];
+const MG_QUEEN: [i32; 64] = [
+    -28,   0,  29,  12,  59,  44,  43,  45,
+    -24, -39,  -5,   1, -16,  57,  28,  54,
+    -13, -17,   7,   8,  29,  56,  47,  57,
+    -27, -27, -16, -16,  -1,  17,  -2,   1,
+     -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+    -14,   2, -11,  -2,  -5,   2,  14,   5,
+    -35,  -8,  11,   2,   8,  15,  -3,   1,
+     -1, -18,  -9,  10, -15, -25, -31, -50,
+];
+const EG_QUEEN: [i32; 64] = [
+     -9,  22,  22,  27,  27,  19,  10,  20,
+    -17,  20,  32,  41,  58,  25,  30,   0,
+    -20,   6,   9,  49,  47,  35,  19,   9,
+      3,  22,  24,  45,  57,  40,  57,  36,
+    -18,  28,  19,  47,  31,  34,  39,  23,
+    -16, -27,  15,   6,   9,  17,  10,   5,
+    -22, -23, -30, -16, -16, -23, -36, -32,
+    -33, -28, -22, -43,  -5, -32, -20, -41,
+];
+const MG_KING: [i32; 64] = [
+    -65,  23,  16, -15, -56, -34,   2,  13,
+     29,  -1, -20,  -7,  -8,  -4, -38, -29,
+     -9,  24,   2, -16, -20,   6,  22, -22,
+    -17, -20, -12, -27, -30, -25, -14, -36,
+    -49,  -1, -27, -39, -46, -44, -33, -51,
+    -14, -14, -22, -46, -44, -30, -15, -27,
+      1,   7,  -8, -64, -43, -16,   9,   8,
+    -15,  36,  12, -54,   8, -28,  24,  14,
+];
+const EG_KING: [i32; 64] = [
+    -74, -35, -18, -18, -11,  15,   4, -17,
+    -12,  17,  14,  17,  17,  38,  23,  11,
+     10,  17,  23,  15,  20,  45,  44,  13,
+     -8,  22,  24,  27,  26,  33,  26,   3,
+    -18,  -4,  21,  24,  27,  23,   9, -11,
+    -19,  -3,  11,  21,  23,  16,   7,  -9,
+    -27, -11,   4,  13,  14,   4,  -5, -17,
+    -53, -34, -21, -11, -28, -14, -24, -43,
+];
+
+// Indexed by Type as usize
+const MG_TABLES: [&[i32; 64]; 6] = [&MG_PAWN, &MG_KNIGHT, &MG_BISHOP, &MG_ROOK, &MG_QUEEN, &MG_KING];
+const EG_TABLES: [&[i32; 64]; 6] = [&EG_PAWN, &EG_KNIGHT, &EG_BISHOP, &EG_ROOK, &EG_QUEEN, &EG_KING];
+
+// Where the square sq is in the tables for a piece of color c. White reads them upside down
+// since they start from a8, black reads them as they are which mirrors them to its side.
+fn table_square(sq: usize, c: Color) -> usize {
+    if c == Color::White {sq ^ 56} else {sq}
+}
+
+// Game phase from MAX_PHASE with every piece on the board down to 0 with only pawns and kings.
+// Promotions can push it over, so it's capped.
+fn phase(b: &Board) -> i32 {
+    let mut phase = 0;
+    for p in [Type::Knight, Type::Bishop, Type::Rook, Type::Queen] {
+        phase += PHASE[p as usize] * b.pieces[p as usize].count_ones() as i32;
+    }
+    phase.min(MAX_PHASE)
+}
+
+// Blends a middlegame and an endgame score by the phase
+fn taper(mg: i32, eg: i32, phase: i32) -> i32 {
+    (mg*phase + eg*(MAX_PHASE - phase))/MAX_PHASE
+}
+
+// Middlegame and endgame halves of a score
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl std::ops::AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        self.mg += other.mg;
+        self.eg += other.eg;
+    }
+}
+
+// Every term of the evaluation for each side, indexed by Color::index and counted in that
+// side's favor. Blending the total by the phase gives what evaluate returns.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Breakdown {
+    pub material: [Score; 2],
+    pub psqt: [Score; 2],
+    pub pawns: [Score; 2],
+    pub mobility: [Score; 2],
+    pub king_safety: [Score; 2],
+    pub phase: i32,
+}
+
+impl Breakdown {
+    pub fn new(b: &Board) -> Breakdown {
+        let mut breakdown = Breakdown { pawns: pawns::evaluate(b), phase: phase(b), ..Breakdown::default() };
+        for c in [Color::White, Color::Black] {
+            for p in [Type::Pawn, Type::Knight, Type::Bishop, Type::Rook, Type::Queen, Type::King] {
+                let mut pieces = b.bitboard(p, c);
+                while pieces != 0 {
+                    let sq = table_square(pieces.trailing_zeros() as usize, c);
+                    pieces &= pieces - 1;
+                    breakdown.material[c.index()] += Score { mg: MG_VALUE[p as usize], eg: EG_VALUE[p as usize] };
+                    breakdown.psqt[c.index()] += Score { mg: MG_TABLES[p as usize][sq], eg: EG_TABLES[p as usize][sq] };
+                }
+            }
+            breakdown.mobility[c.index()] = mobility(b, c);
+            breakdown.king_safety[c.index()] = king_safety(b, c);
+        }
+        breakdown
+    }
+
+    // Each term with its name
+    pub fn terms(&self) -> [(&'static str, [Score; 2]); 5] {
+        [
+            ("Material", self.material),
+            ("PSQT", self.psqt),
+            ("Pawns", self.pawns),
+            ("Mobility", self.mobility),
+            ("King safety", self.king_safety),
+        ]
+    }
+
+    // Everything added up, white's terms less black's
+    pub fn total(&self) -> Score {
+        let mut total = Score::default();
+        for (_, [white, black]) in self.terms() {
+            total += Score { mg: white.mg - black.mg, eg: white.eg - black.eg };
+        }
+        total
+    }
+
+    pub fn score(&self) -> i32 {
+        let total = self.total();
+        taper(total.mg, total.eg, self.phase)
+    }
+}
+
+// Squares each piece attacks, with occ as the pieces that block sliders
+fn attacks(p: Type, sq: usize, occ: u64) -> u64 {
+    match p {
+        Type::Knight => KNIGHT_ATTACKS[sq],
+        Type::Bishop => bishop_attacks(sq, occ),
+        Type::Rook => rook_attacks(sq, occ),
+        Type::Queen => bishop_attacks(sq, occ) | rook_attacks(sq, occ),
+        Type::King => KING_ATTACKS[sq],
+        Type::Pawn => 0,
+    }
+}
+
+// Squares the pawns of color c attack
+fn pawn_attacks(b: &Board, c: Color) -> u64 {
+    let mut pawns = b.bitboard(Type::Pawn, c);
+    let mut attacks = 0;
+    while pawns != 0 {
+        attacks |= PAWN_ATTACKS[c.index()][pawns.trailing_zeros() as usize];
+        pawns &= pawns - 1;
+    }
+    attacks
+}
+
+fn mobility(b: &Board, c: Color) -> Score {
+    let available = !b.colors[c.index()] & !pawn_attacks(b, !c);
+    let mut score = Score::default();
+    for p in [Type::Knight, Type::Bishop, Type::Rook, Type::Queen] {
+        let mut pieces = b.bitboard(p, c);
+        while pieces != 0 {
+            let sq = pieces.trailing_zeros() as usize;
+            pieces &= pieces - 1;
+            let moves = (attacks(p, sq, b.occupied()) & available).count_ones() as i32 - MOBILITY_BASE[p as usize];
+            score += Score { mg: MOBILITY_MG[p as usize]*moves, eg: MOBILITY_EG[p as usize]*moves };
+        }
+    }
+    score
+}
+
+// Only counts in the middlegame, in the endgame the king is meant to come out
+fn king_safety(b: &Board, c: Color) -> Score {
+    let king = b.bitboard(Type::King, c);
+    if king == 0 {
+        return Score::default();
+    }
+    let sq = king.trailing_zeros() as usize;
+    let zone = KING_ATTACKS[sq] | king;
+    let (mut attackers, mut weight) = (0, 0);
+    for p in [Type::Knight, Type::Bishop, Type::Rook, Type::Queen] {
+        let mut pieces = b.bitboard(p, !c);
+        while pieces != 0 {
+            let hits = attacks(p, pieces.trailing_zeros() as usize, b.occupied()) & zone;
+            pieces &= pieces - 1;
+            if hits != 0 {
+                attackers += 1;
+                weight += ATTACK_WEIGHT[p as usize] * hits.count_ones() as i32;
+            }
+        }
+    }
+    let mut mg = if attackers >= MIN_ATTACKERS {-KING_DANGER[weight.min(63) as usize]} else {0};
+    let own = b.bitboard(Type::Pawn, c);
+    let all = b.pieces[Type::Pawn as usize];
+    let (x, y) = (sq as i32 % 8, sq as u32 / 8);
+    for file in (x - 1).max(0)..=(x + 1).min(7) {
+        let file = FILE_A << file;
+        // Nearest pawn of ours in front of the king on this file
+        let shield = own & file & ahead(c, y);
+        let distance = match c {
+            _ if shield == 0 => u32::MAX,
+            Color::White => shield.trailing_zeros()/8 - y,
+            Color::Black => y - (63 - shield.leading_zeros())/8,
+        };
+        mg += match distance {
+            1 => 0,
+            2 => SHIELD_ADVANCED,
+            _ => SHIELD_MISSING,
+        };
+        if all & file == 0 {
+            mg += OPEN_FILE;
+        }
+        else if own & file == 0 {
+            mg += HALF_OPEN_FILE;
+        }
+    }
+    Score { mg, eg: 0 }
+}
+
+// A table of the terms with white, black and white less black for each, then the total blended
+// by the phase
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>11} | {:^13} | {:^13} | {:>9}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:>11} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", "", "MG", "EG", "MG", "EG", "MG", "EG")?;
+        writeln!(f, "{:-<11}-+-{:-<13}-+-{:-<13}-+-{:-<13}", "", "", "", "")?;
+        for (name, [white, black]) in self.terms() {
+            writeln!(f, "{:>11} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", name, white.mg, white.eg, black.mg, black.eg, white.mg - black.mg, white.eg - black.eg)?;
+        }
+        writeln!(f, "{:-<11}-+-{:-<13}-+-{:-<13}-+-{:-<13}", "", "", "", "")?;
+        let total = self.total();
+        writeln!(f, "{:>11} | {:>13} | {:>13} | {:>6} {:>6}", "Total", "", "", total.mg, total.eg)?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{} (middlegame weight)", self.phase, MAX_PHASE)?;
+        write!(f, "Evaluation: {} cp from white's point of view", self.score())
+    }
+}
+
+impl Evaluate for Board {
+    fn evaluate(&self) -> i32 {
+        self.breakdown().score()
+    }
+
+    fn breakdown(&self) -> Breakdown {
+        Breakdown::new(self)
+    }
+}
@@ -1,14 +1,69 @@
 // use std::fs::{OpenOptions};
 // use std::io::{Write};
 
-use core::time;
-use std::{thread, sync::{Arc}};
+use std::{fmt, thread, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::{Duration, Instant}};
 use indicatif::{ProgressBar, ProgressStyle};
 
+mod bitboard;
+mod eval;
+mod pawns;
+mod tt;
+mod uci;
+mod zobrist;
+
+use bitboard::{bishop_attacks, rook_attacks, DARK_SQUARES, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
+use tt::{from_tt, move_code, to_tt, Bound, Entry, TT};
+use zobrist::KEYS;
+
 const WIDTH:i32 = 8;
-const WHITE:f32 = 1.;
-const NONE:f32 = 0.;
-const BLACK:f32= -1.;
+
+// Score of being checkmated right now, mates further away score closer to zero. Scores are in centipawns.
+const MATE:i32 = 100000;
+
+// Deepest iterative deepening goes when only time limits it
+const MAX_DEPTH:i32 = 64;
+// Depth the search command goes to when not given one
+const SEARCH_DEPTH:i32 = 6;
+// Bigger than any score, the window the search starts with
+const INFINITY:i32 = 999999;
+// Width of the window PVS uses to prove a move is no better than alpha
+const NULL_WINDOW:i32 = 1;
+// Half the width of the first aspiration window, and the depth they start being used at
+const ASPIRATION_WINDOW:i32 = 25;
+const ASPIRATION_DEPTH:i32 = 4;
+// Null move pruning is tried from this depth on, with the pass searched this much shallower
+// on top of the ply it uses up and a ply more for every 6 of depth
+const NULL_MOVE_DEPTH:i32 = 3;
+const NULL_MOVE_REDUCTION:i32 = 2;
+// Futility pruning, both kinds, works this many plies from the leaves with this margin per ply
+const FUTILITY_DEPTH:i32 = 3;
+const FUTILITY_MARGIN:i32 = 100;
+// Late move reductions start after this many moves and from this depth
+const LMR_MOVES:usize = 3;
+const LMR_DEPTH:i32 = 3;
+// Deepest ply the search keeps killer moves for
+const MAX_PLY:usize = 128;
+// History scores get halved once one passes this
+const HISTORY_MAX:i32 = 1 << 20;
+
+// Castling rights, one bit each
+const WHITE_KINGSIDE:u8 = 1;
+const WHITE_QUEENSIDE:u8 = 2;
+const BLACK_KINGSIDE:u8 = 4;
+const BLACK_QUEENSIDE:u8 = 8;
+
+const START_FEN:&str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Reference positions from https://www.chessprogramming.org/Perft_Results, the tests check perft
+// on them and the bench command times the search on them
+const KIWIPETE:&str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION_3:&str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION_4:&str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+const POSITION_5:&str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION_6:&str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+const BENCH:[&str; 6] = [START_FEN, KIWIPETE, POSITION_3, POSITION_4, POSITION_5, POSITION_6];
+// Depth the bench command searches each position to when not given one
+const BENCH_DEPTH:i32 = 5;
 
 /* Board Layout
 - - - - - - - - - - - - - - -
@@ -31,9 +86,41 @@ const BLACK:f32= -1.;
  */
 
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    // Which way pawns go and which way the score is counted, 1 for white and -1 for black
+    fn sign(self) -> i32 {
+        match self {
+            Color::White => 1,
+            Color::Black => -1,
+        }
+    }
+
+    // Index into Board::colors
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// The other color
+impl std::ops::Not for Color {
+    type Output = Color;
+
+    fn not(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Type {
-    None,
     Pawn,
     Knight,
     Bishop,
@@ -43,554 +130,2021 @@ enum Type {
 }
 
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Piece {
     p: Type,
-    c: f32,
+    c: Color,
 }
 
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Move {
     p0: Piece,
     x0: i32,
     y0: i32,
-    p1: Piece,
+    // Whatever was on the destination square
+    p1: Option<Piece>,
     x1: i32,
     y1: i32,
     capture: bool,
-    promotion: bool,
+    // What a pawn turns into on the last rank
+    promotion: Option<Type>,
     enpassant: bool,
     castle: bool,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GameResult {
+    Ongoing,
+    // Holds the color of the winner
+    Checkmate(Color),
+    Stalemate,
+    Draw(Draw),
+}
+
+// Draws other than stalemate. Threefold repetition and the fifty move rule only let a player
+// claim the draw while the fivefold and seventy-five move ones end the game on their own, but
+// either way the engine takes the draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Draw {
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    FiftyMoves,
+    SeventyFiveMoves,
+    // Neither side has the pieces to ever checkmate
+    InsufficientMaterial,
+}
+
 #[derive(Clone, Debug)]
 struct Board {
-    c: f32,
-    b: [Piece; 64],
+    c: Color,
+    b: [Option<Piece>; 64],
+    // Bitboards of every piece type (indexed by Type as usize) and of each color (indexed by Color::index)
+    pieces: [u64; 6],
+    colors: [u64; 2],
     moves: Vec<Move>,
     moves_made: Vec<Move>,
-    winner: i32,
+    // Hashes of the positions before each move made, for spotting repetitions
+    history: Vec<u64>,
+    result: GameResult,
+    castling: u8,
+    // Square a pawn can be captured on en passant, set right after a double push
+    ep: Option<(i32, i32)>,
+    // Half moves since the last capture or pawn move
+    halfmove: u32,
+    // Starts at 1 and goes up after every black move
+    fullmove: u32,
+    // Zobrist hash of the position, kept up to date by write and make_move
+    hash: u64,
+    // Zobrist hash of the pawns alone, for the pawn structure cache
+    pawn_hash: u64,
 }
 
-trait Read {
-    fn read(&self, x: i32, y: i32) -> Option<Piece>;
+// Bitboard with only the square (x, y) set
+fn bit(x: i32, y: i32) -> u64 {
+    1 << (y*WIDTH + x)
 }
-impl Read for Board {
-    fn read(&self, x: i32, y: i32) -> Option<Piece> {
-        if x > 7 || x < 0 || y > 7 || y < 0 {
-            return None;
-        }
-        return Some(self.b[(y*WIDTH + x) as usize]);
+
+impl Board {
+    // Squares holding pieces of type p and color c
+    fn bitboard(&self, p: Type, c: Color) -> u64 {
+        self.pieces[p as usize] & self.colors[c.index()]
+    }
+
+    fn occupied(&self) -> u64 {
+        self.colors[0] | self.colors[1]
     }
 }
 
 trait BWrite {
-    fn write(&mut self, x: i32, y: i32, p: Piece);
+    fn write(&mut self, x: i32, y: i32, p: Option<Piece>);
 }
 impl BWrite for Board {
-    fn write(&mut self, x: i32, y: i32, p: Piece) {
-        self.b[(y*WIDTH + x) as usize] = p;
+    // Keeps the bitboards in step with the squares
+    fn write(&mut self, x: i32, y: i32, p: Option<Piece>) {
+        let sq = (y*WIDTH + x) as usize;
+        if let Some(old) = self.b[sq] {
+            self.pieces[old.p as usize] &= !(1 << sq);
+            self.colors[old.c.index()] &= !(1 << sq);
+            self.hash ^= KEYS.pieces[old.c.index()][old.p as usize][sq];
+            if old.p == Type::Pawn {
+                self.pawn_hash ^= KEYS.pieces[old.c.index()][Type::Pawn as usize][sq];
+            }
+        }
+        if let Some(p) = p {
+            self.pieces[p.p as usize] |= 1 << sq;
+            self.colors[p.c.index()] |= 1 << sq;
+            self.hash ^= KEYS.pieces[p.c.index()][p.p as usize][sq];
+            if p.p == Type::Pawn {
+                self.pawn_hash ^= KEYS.pieces[p.c.index()][Type::Pawn as usize][sq];
+            }
+        }
+        self.b[sq] = p;
     }
 }
 
+// Every piece of either color that attacks the square, with occ as the pieces that block sliders
+fn attackers(b: &Board, sq: usize, occ: u64) -> u64 {
+    let pawns = b.pieces[Type::Pawn as usize];
+    let knights = b.pieces[Type::Knight as usize];
+    let bishops = b.pieces[Type::Bishop as usize];
+    let rooks = b.pieces[Type::Rook as usize];
+    let queens = b.pieces[Type::Queen as usize];
+    let kings = b.pieces[Type::King as usize];
+    // A white pawn attacks the square if a black pawn on it would attack the white pawn, and the other way around
+    (PAWN_ATTACKS[1][sq] & pawns & b.colors[0])
+        | (PAWN_ATTACKS[0][sq] & pawns & b.colors[1])
+        | (KNIGHT_ATTACKS[sq] & knights)
+        | (KING_ATTACKS[sq] & kings)
+        | (bishop_attacks(sq, occ) & (bishops | queens))
+        | (rook_attacks(sq, occ) & (rooks | queens))
+}
+
 trait Threatened {
-    fn threatened(&self, x: i32, y: i32, m: Move) -> bool;
+    fn threatened(&self, x: i32, y: i32, by: Color) -> bool;
+    fn in_check(&self, c: Color) -> bool;
 }
-// impl Threatened for Board {
-//     fn threatened(&self, x: i32, y: i32, m: Move) -> bool{
+impl Threatened for Board {
+    // Whether any piece of color `by` attacks the square (x, y)
+    fn threatened(&self, x: i32, y: i32, by: Color) -> bool {
+        attackers(self, (y*WIDTH + x) as usize, self.occupied()) & self.colors[by.index()] != 0
+    }
 
-        
-//     }
-// }
+    // Whether the king of color `c` is attacked
+    fn in_check(&self, c: Color) -> bool {
+        let king = self.bitboard(Type::King, c);
+        king != 0 && attackers(self, king.trailing_zeros() as usize, self.occupied()) & self.colors[(!c).index()] != 0
+    }
+}
 
-fn calc_pawn(b:&mut Board, x: i32, y: i32, piece: Piece){
+// Whether the move keeps our own king out of check. Only the bits that matter get moved
+// rather than playing the move out on a copy of the board.
+fn legal(b: &Board, m: &Move) -> bool {
+    // Castling already checked every square the king crosses
+    if m.castle {
+        return true;
+    }
+    let from = bit(m.x0, m.y0);
+    let to = bit(m.x1, m.y1);
+    // The captured pawn is beside us for en passant
+    let captured = if m.enpassant {bit(m.x1, m.y0)} else {to};
+    let occ = (b.occupied() & !from & !captured) | to;
+    let king = if m.p0.p == Type::King {to} else {b.bitboard(Type::King, b.c)};
+    attackers(b, king.trailing_zeros() as usize, occ) & b.colors[(!b.c).index()] & !captured == 0
+}
+
+// Squares a piece of color c may move to, only the other side's pieces without quiet moves
+fn targets(b: &Board, c: Color, quiet: bool) -> u64 {
+    if quiet {!b.colors[c.index()]} else {b.colors[(!c).index()]}
+}
+
+// Pushes a move to every square in targets
+fn push_targets(b: &mut Board, x: i32, y: i32, piece: Piece, mut targets: u64) {
+    while targets != 0 {
+        let sq = targets.trailing_zeros() as i32;
+        targets &= targets - 1;
+        let piece_dest = b.b[sq as usize];
+        b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: sq%WIDTH, y1: sq/WIDTH, capture: piece_dest.is_some(), promotion: None, enpassant: false, castle: false});
+    }
+}
+
+// Pushes a pawn move, or one move for each piece it can promote to when it reaches the last rank
+fn push_pawn(b: &mut Board, m: Move, is_last: bool) {
+    if !is_last {
+        b.moves.push(m);
+        return;
+    }
+    for p in [Type::Queen, Type::Knight, Type::Rook, Type::Bishop] {
+        b.moves.push(Move{promotion: Some(p), ..m});
+    }
+}
+
+// Without quiet only captures and promotions get generated, the rest of the calc functions work the same way
+fn calc_pawn(b:&mut Board, x: i32, y: i32, piece: Piece, quiet: bool){
     let color = piece.c;
-    let is_last = (y + color as i32)%(WIDTH-1) == 0;
-    if let Some(piece_dest) = b.read(x, y + color as i32){
-        if piece_dest.p == Type::None {
-            // Move Forward One (-1 or 1 depending on color)
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x, y1: y + (color as i32), capture: false, promotion: is_last, enpassant: false, castle: false});
-            // Move Forward Two (-2 or 2 depending on color)
-            if y == (3.5 - 2.5*color) as i32 {
-                if let Some(piece_dest) = b.read(x, y + (color as i32)*2) {
-                    b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x, y1: y + (color as i32)*2, capture: false, promotion: false, enpassant: false, castle: false});
-                }
-            }
+    let forward = color.sign();
+    let is_last = (y + forward)%(WIDTH-1) == 0;
+    let occ = b.occupied();
+    // Move Forward One (-1 or 1 depending on color)
+    if (quiet || is_last) && occ & bit(x, y + forward) == 0 {
+        push_pawn(b, Move{p0: piece, x0: x, y0: y, p1: None, x1: x, y1: y + forward, capture: false, promotion: None, enpassant: false, castle: false}, is_last);
+        // Move Forward Two (-2 or 2 depending on color)
+        let start = if color == Color::White {1} else {6};
+        if quiet && y == start && occ & bit(x, y + forward*2) == 0 {
+            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: None, x1: x, y1: y + forward*2, capture: false, promotion: None, enpassant: false, castle: false});
         }
     }
-    // Capture Forward (-1 or 1 depending on color) right
-    if let Some(piece_dest) = b.read(x+1, y + color as i32) {
-        if piece_dest.p != Type::None && piece_dest.c != color {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x+1, y1: y + (color as i32), capture: true, promotion: is_last, enpassant: false, castle: false});
-        }
+    // Capture Forward (-1 or 1 depending on color) left and right
+    let attacks = PAWN_ATTACKS[color.index()][(y*WIDTH + x) as usize];
+    let mut targets = attacks & b.colors[(!color).index()];
+    while targets != 0 {
+        let sq = targets.trailing_zeros() as i32;
+        targets &= targets - 1;
+        let piece_dest = b.b[sq as usize];
+        push_pawn(b, Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: sq%WIDTH, y1: sq/WIDTH, capture: true, promotion: None, enpassant: false, castle: false}, is_last);
     }
-    // Capture Forward (-1 or 1 depending on color) left
-    if let Some(piece_dest) = b.read(x-1, y + color as i32) {
-        if piece_dest.p != Type::None && piece_dest.c != color {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x-1, y1: y + (color as i32), capture: true, promotion: is_last, enpassant: false, castle: false});
+    // En Passant, onto the square an enemy pawn skipped with its double push last move
+    if let Some((ep_x, ep_y)) = b.ep {
+        if attacks & bit(ep_x, ep_y) != 0 {
+            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: None, x1: ep_x, y1: ep_y, capture: true, promotion: None, enpassant: true, castle: false});
         }
     }
-    // En Passant Left
-    if let Some(piece_dest) = b.read(x-1, y + color as i32) {
-        if piece_dest.p == Type::None && b.moves_made.len() != 0 {
-            if b.moves_made[b.moves_made.len() - 1] == (Move{p0: Piece{p: Type::Pawn, c: color * -1.}, x0: x - 1, y0: y + (color as i32) * 2, p1: Piece{p: Type::None, c: NONE}, x1: x - 1, y1: y, capture: false, promotion: false, enpassant: false, castle: false}) {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x-1, y1: y + (color as i32), capture: false, promotion: false, enpassant: true, castle: false});
-            }
+}
+
+fn calc_knight(b:&mut Board, x: i32, y: i32, piece: Piece, quiet: bool){
+    let targets = KNIGHT_ATTACKS[(y*WIDTH + x) as usize] & targets(b, piece.c, quiet);
+    push_targets(b, x, y, piece, targets);
+}
+
+fn calc_bishop(b:&mut Board, x: i32, y: i32, piece: Piece, quiet: bool) {
+    let targets = bishop_attacks((y*WIDTH + x) as usize, b.occupied()) & targets(b, piece.c, quiet);
+    push_targets(b, x, y, piece, targets);
+}
+
+fn calc_rook(b:&mut Board, x: i32, y: i32, piece: Piece, quiet: bool) {
+    let targets = rook_attacks((y*WIDTH + x) as usize, b.occupied()) & targets(b, piece.c, quiet);
+    push_targets(b, x, y, piece, targets);
+}
+
+fn calc_king(b:&mut Board, x: i32, y: i32, piece: Piece, quiet: bool) {
+    let targets = KING_ATTACKS[(y*WIDTH + x) as usize] & targets(b, piece.c, quiet);
+    push_targets(b, x, y, piece, targets);
+    // Castling (0 for white, 7 for black)
+    let home = if piece.c == Color::White {0} else {7};
+    if !quiet || x != 4 || y != home || b.threatened(x, y, !piece.c) {
+        return;
+    }
+    let (kingside, queenside) = if piece.c == Color::White {(WHITE_KINGSIDE, WHITE_QUEENSIDE)} else {(BLACK_KINGSIDE, BLACK_QUEENSIDE)};
+    let occ = b.occupied();
+    let rooks = b.bitboard(Type::Rook, piece.c);
+    // Kingside, the king may not pass through or land on an attacked square
+    if b.castling & kingside != 0 && rooks & bit(7, y) != 0 && occ & (bit(5, y) | bit(6, y)) == 0
+        && !b.threatened(5, y, !piece.c) && !b.threatened(6, y, !piece.c) {
+        b.moves.push(Move{p0: piece, x0: x, y0: y, p1: None, x1: 6, y1: y, capture: false, promotion: None, enpassant: false, castle: true});
+    }
+    // Queenside, the b file square only has to be empty
+    if b.castling & queenside != 0 && rooks & bit(0, y) != 0 && occ & (bit(1, y) | bit(2, y) | bit(3, y)) == 0
+        && !b.threatened(3, y, !piece.c) && !b.threatened(2, y, !piece.c) {
+        b.moves.push(Move{p0: piece, x0: x, y0: y, p1: None, x1: 2, y1: y, capture: false, promotion: None, enpassant: false, castle: true});
+    }
+}
+
+// Castling rights lost when a piece moves from or to the square (x, y)
+fn castling_lost(x: i32, y: i32) -> u8 {
+    match (x, y) {
+        (4, 0) => WHITE_KINGSIDE | WHITE_QUEENSIDE,
+        (7, 0) => WHITE_KINGSIDE,
+        (0, 0) => WHITE_QUEENSIDE,
+        (4, 7) => BLACK_KINGSIDE | BLACK_QUEENSIDE,
+        (7, 7) => BLACK_KINGSIDE,
+        (0, 7) => BLACK_QUEENSIDE,
+        _ => 0,
+    }
+}
+
+
+
+// Every pseudo-legal move for the side to move, or only the captures and promotions without quiet
+fn generate(b: &mut Board, quiet: bool) {
+    b.moves.clear();
+    let mut own = b.colors[b.c.index()];
+    while own != 0 {
+        let i = own.trailing_zeros() as i32;
+        own &= own - 1;
+        let Some(piece) = b.b[i as usize] else {
+            continue;
+        };
+        let x = i%WIDTH;
+        let y = i/WIDTH;
+        match piece.p {
+            Type::Pawn => {
+                calc_pawn(b, x, y, piece, quiet);
+            },
+            Type::Knight => {
+                calc_knight(b, x, y, piece, quiet);
+            },
+            Type::Bishop => {
+                calc_bishop(b, x, y, piece, quiet);
+            },
+            Type::Rook => {
+                calc_rook(b, x, y, piece, quiet);
+            },
+            Type::Queen => {
+                calc_bishop(b, x, y, piece, quiet);
+                calc_rook(b, x, y, piece, quiet);
+            },
+            Type::King => {
+                calc_king(b, x, y, piece, quiet)
+            },
         }
     }
-    // En Passant Right
-    if let Some(piece_dest) = b.read(x+1, y + color as i32) {
-        if piece_dest.p == Type::None && b.moves_made.len() != 0 {
-            if b.moves_made[b.moves_made.len() - 1] == (Move{p0: Piece{p: Type::Pawn, c: color * -1.}, x0: x + 1, y0: y + (color as i32) * 2, p1: Piece{p: Type::None, c: NONE}, x1: x + 1, y1: y, capture: false, promotion: false, enpassant: false, castle: false}) {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x+1, y1: y + (color as i32), capture: false, promotion: false, enpassant: true, castle: false});
-            }
+    // Only keep the moves that don't leave our own king in check
+    let mut moves = std::mem::take(&mut b.moves);
+    moves.retain(|m| legal(b, m));
+    b.moves = moves;
+}
+
+trait Calculate {
+    fn calculate(&mut self);
+    fn calculate_captures(&mut self);
+}
+impl Calculate for Board {
+    fn calculate(&mut self) {
+        generate(self, true);
+        // No legal moves means the game is over, and mate counts even when it comes on the last move before a draw
+        self.result = if self.moves.is_empty() && self.in_check(self.c) {
+            GameResult::Checkmate(!self.c)
         }
+        else if self.moves.is_empty() {
+            GameResult::Stalemate
+        }
+        else if let Some(draw) = self.draw() {
+            GameResult::Draw(draw)
+        }
+        else {
+            GameResult::Ongoing
+        };
+    }
+
+    // Legal captures and promotions only, for the quiescence search. result is left alone since
+    // having none of these says nothing about the game being over.
+    fn calculate_captures(&mut self) {
+        generate(self, false);
     }
 }
 
-fn calc_knight(b:&mut Board, x: i32, y: i32, piece: Piece){
-    // Move Right 2 Up 1
-    if let Some(piece_dest) = b.read(x + 2, y + 1) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + 2, y1: y + 1, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+trait Evaluate {
+    // In centipawns, positive when white is better
+    fn evaluate(&self) -> i32;
+    // Every term that went into evaluate, for each side
+    fn breakdown(&self) -> eval::Breakdown;
+}
+
+// What make_move can't work out again when taking a move back
+#[derive(Clone, Copy, Debug)]
+struct Undo {
+    // The piece that got taken, which is beside the pawn for en passant
+    captured: Option<Piece>,
+    castling: u8,
+    ep: Option<(i32, i32)>,
+    halfmove: u32,
+    result: GameResult,
+    hash: u64,
+}
+
+impl Board {
+    // Plays the move in place, hand the Undo back to unmake_move to take it back
+    fn make_move(&mut self, m: &Move) -> Undo {
+        // Open File
+        // let file_name = "log.txt";
+        // let mut file = OpenOptions::new()
+        //     .read(true)
+        //     .write(true)
+        //     .create(false)
+        //     .append(true)
+        //     .open(file_name).unwrap();
+
+        // Log Moves into File
+        // match write!(file, "{}", format!("{:?}{:?}{:?}\n", m.p0.p, m.x1, m.y1)) {
+        //     Ok(_) => (),
+        //     Err(_) => println!("Problem writing move {:?}", file),
+        // }
+
+        let mut undo = Undo { captured: m.p1, castling: self.castling, ep: self.ep, halfmove: self.halfmove, result: self.result, hash: self.hash };
+        // If Promotion for Pawn
+        if let Some(p) = m.promotion {
+            self.write(m.x1, m.y1, Some(Piece{p, c: m.p0.c}));
+            self.write(m.x0, m.y0, None);
+        }
+        // Else normal move
+        else {
+        self.write(m.x1, m.y1, Some(m.p0));
+        self.write(m.x0, m.y0, None);
+        }
+        // If En Passant, the captured pawn is beside us rather than on the square we moved to
+        if m.enpassant {
+            undo.captured = self.b[(m.y0*WIDTH + m.x1) as usize];
+            self.write(m.x1, m.y0, None);
+        }
+        // If Castle, bring the rook over to the other side of the king
+        if m.castle {
+            let (rook_from, rook_to) = if m.x1 == 6 {(7, 5)} else {(0, 3)};
+            let rook = self.b[(m.y1*WIDTH + rook_from) as usize];
+            self.write(rook_to, m.y1, rook);
+            self.write(rook_from, m.y1, None);
+        }
+        // Pieces were hashed as they got written, the rest is swapped out here
+        self.hash ^= KEYS.castling[self.castling as usize];
+        self.castling &= !(castling_lost(m.x0, m.y0) | castling_lost(m.x1, m.y1));
+        self.hash ^= KEYS.castling[self.castling as usize];
+        if let Some((x, _)) = self.ep {
+            self.hash ^= KEYS.ep[x as usize];
+        }
+        // Remember the skipped square after a double push
+        self.ep = if m.p0.p == Type::Pawn && (m.y1 - m.y0).abs() == 2 {Some((m.x0, (m.y0 + m.y1)/2))} else {None};
+        if let Some((x, _)) = self.ep {
+            self.hash ^= KEYS.ep[x as usize];
+        }
+        if m.p0.p == Type::Pawn || m.capture {
+            self.halfmove = 0;
+        }
+        else {
+            self.halfmove += 1;
+        }
+        if m.p0.c == Color::Black {
+            self.fullmove += 1;
         }
+        self.moves_made.push(*m);
+        self.history.push(undo.hash);
+        self.c = !self.c;
+        self.hash ^= KEYS.side;
+        undo
     }
-    // Move Right 2 Down 1
-    if let Some(piece_dest) = b.read(x + 2, y - 1) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + 2, y1: y - 1, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Takes back the last move played with make_move
+    fn unmake_move(&mut self, m: &Move, undo: Undo) {
+        self.c = !self.c;
+        self.moves_made.pop();
+        self.history.pop();
+        if m.p0.c == Color::Black {
+            self.fullmove -= 1;
+        }
+        self.halfmove = undo.halfmove;
+        self.ep = undo.ep;
+        self.castling = undo.castling;
+        self.result = undo.result;
+        if m.castle {
+            let (rook_from, rook_to) = if m.x1 == 6 {(7, 5)} else {(0, 3)};
+            let rook = self.b[(m.y1*WIDTH + rook_to) as usize];
+            self.write(rook_from, m.y1, rook);
+            self.write(rook_to, m.y1, None);
+        }
+        // The moving piece is still a pawn in the move if it promoted
+        self.write(m.x0, m.y0, Some(m.p0));
+        if m.enpassant {
+            self.write(m.x1, m.y1, None);
+            self.write(m.x1, m.y0, undo.captured);
+        }
+        else {
+            self.write(m.x1, m.y1, undo.captured);
         }
+        self.hash = undo.hash;
     }
-    // Move Down 2 Right 1
-    if let Some(piece_dest) = b.read(x + 1, y - 2) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + 1, y1: y - 2, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Passes the turn without moving, for null move pruning. Hand the Undo back to unmake_null_move.
+    fn make_null_move(&mut self) -> Undo {
+        let undo = Undo { captured: None, castling: self.castling, ep: self.ep, halfmove: self.halfmove, result: self.result, hash: self.hash };
+        if let Some((x, _)) = self.ep.take() {
+            self.hash ^= KEYS.ep[x as usize];
         }
+        self.halfmove += 1;
+        self.history.push(undo.hash);
+        self.c = !self.c;
+        self.hash ^= KEYS.side;
+        undo
+    }
+
+    fn unmake_null_move(&mut self, undo: Undo) {
+        self.c = !self.c;
+        self.history.pop();
+        self.halfmove = undo.halfmove;
+        self.ep = undo.ep;
+        self.hash = undo.hash;
     }
-    // Move Down 2 Left 1
-    if let Some(piece_dest) = b.read(x - 1, y - 2) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - 1, y1: y - 2, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // How many times the position came up before. Only positions since the last capture or pawn
+    // move can be the same, and only every other one has the same side to move.
+    fn repetitions(&self) -> usize {
+        self.history.iter().rev().take(self.halfmove as usize).skip(1).step_by(2).filter(|h| **h == self.hash).count()
+    }
+
+    // Neither side can mate: kings alone, a single minor piece, or only bishops all on one color
+    fn insufficient_material(&self) -> bool {
+        let pawns_rooks_queens = self.pieces[Type::Pawn as usize] | self.pieces[Type::Rook as usize] | self.pieces[Type::Queen as usize];
+        if pawns_rooks_queens != 0 {
+            return false;
         }
+        let knights = self.pieces[Type::Knight as usize];
+        let bishops = self.pieces[Type::Bishop as usize];
+        (knights | bishops).count_ones() <= 1
+            || (knights == 0 && (bishops & DARK_SQUARES == 0 || bishops & !DARK_SQUARES == 0))
     }
-    // Move Left 2 Down 1
-    if let Some(piece_dest) = b.read(x - 2, y - 1) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - 2, y1: y - 1, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Whichever draw applies to the position, the ones that end the game on their own first
+    fn draw(&self) -> Option<Draw> {
+        if self.insufficient_material() {
+            return Some(Draw::InsufficientMaterial);
+        }
+        let repetitions = self.repetitions();
+        if repetitions >= 4 {
+            Some(Draw::FivefoldRepetition)
+        }
+        else if self.halfmove >= 150 {
+            Some(Draw::SeventyFiveMoves)
+        }
+        else if repetitions >= 2 {
+            Some(Draw::ThreefoldRepetition)
+        }
+        else if self.halfmove >= 100 {
+            Some(Draw::FiftyMoves)
+        }
+        else {
+            None
         }
     }
-    // Move Left 2 Up 1
-    if let Some(piece_dest) = b.read(x - 2, y + 1) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - 2, y1: y + 1, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Works the Zobrist hash out from scratch rather than updating it
+    fn zobrist(&self) -> u64 {
+        let mut hash = 0;
+        for (sq, piece) in self.b.iter().enumerate() {
+            if let Some(piece) = piece {
+                hash ^= KEYS.pieces[piece.c.index()][piece.p as usize][sq];
+            }
         }
+        if self.c == Color::Black {
+            hash ^= KEYS.side;
+        }
+        hash ^= KEYS.castling[self.castling as usize];
+        if let Some((x, _)) = self.ep {
+            hash ^= KEYS.ep[x as usize];
+        }
+        hash
     }
-    // Move Up 2 Left 1
-    if let Some(piece_dest) = b.read(x - 1, y + 2) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - 1, y1: y + 2, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Same for the pawn hash
+    fn pawn_zobrist(&self) -> u64 {
+        let mut hash = 0;
+        for (sq, piece) in self.b.iter().enumerate() {
+            if let Some(Piece { p: Type::Pawn, c }) = piece {
+                hash ^= KEYS.pieces[c.index()][Type::Pawn as usize][sq];
+            }
         }
+        hash
     }
-    // Move Up 2 Left 1
-    if let Some(piece_dest) = b.read(x + 1, y + 2) {
-        if piece_dest.c != piece.c {
-            b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + 1, y1: y + 2, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+}
+
+// Plays the move on a copy of the board, for when the original is still needed outside of the search
+fn domove(b: &Board, m: &Move) -> Board {
+    let mut board = b.clone();
+    board.make_move(m);
+    board
+}
+
+
+fn setup() -> Board {
+    Board::from_fen(START_FEN).unwrap()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FenError {
+    // Name of the field that is missing
+    MissingField(&'static str),
+    TooManyFields,
+    // Number of ranks found instead of 8
+    RankCount(usize),
+    // Rank (8 to 1) that doesn't describe exactly 8 squares
+    RankLength(i32),
+    Piece(char),
+    // Color without exactly one king
+    KingCount(Color),
+    // Rank (1 or 8) with a pawn on it, pawns promote before they get there
+    PawnOnBackRank(i32),
+    SideToMove(String),
+    // The king of the side that just moved is in check, which no legal move leaves behind
+    OpponentInCheck,
+    Castling(String),
+    EnPassant(String),
+    Clock(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {} field", field),
+            FenError::TooManyFields => write!(f, "too many fields"),
+            FenError::RankCount(n) => write!(f, "expected 8 ranks but found {}", n),
+            FenError::RankLength(rank) => write!(f, "rank {} does not have 8 squares", rank),
+            FenError::Piece(c) => write!(f, "unknown piece '{}'", c),
+            FenError::KingCount(c) => write!(f, "{} must have exactly one king", if *c == Color::White {"white"} else {"black"}),
+            FenError::PawnOnBackRank(rank) => write!(f, "pawn on rank {}", rank),
+            FenError::SideToMove(s) => write!(f, "side to move must be 'w' or 'b', not '{}'", s),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            FenError::Castling(s) => write!(f, "invalid castling rights '{}'", s),
+            FenError::EnPassant(s) => write!(f, "invalid en passant square '{}'", s),
+            FenError::Clock(s) => write!(f, "invalid move clock '{}'", s),
         }
     }
 }
 
-fn calc_bishop(b:&mut Board, x: i32, y: i32, piece: Piece) {
-    // Up Right
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x + i,y + i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + i, y1: y + i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
-            }
-            else {
-                break;
-            }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+impl std::error::Error for FenError {}
+
+// Long algebraic notation as used by UCI, like e2e4 or e7e8q
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let promotion = match self.promotion {
+            Some(Type::Knight) => "n",
+            Some(Type::Bishop) => "b",
+            Some(Type::Rook) => "r",
+            Some(Type::Queen) => "q",
+            _ => "",
+        };
+        write!(f, "{}{}{}", square_name(self.x0, self.y0), square_name(self.x1, self.y1), promotion)
+    }
+}
+
+// FEN letter of a piece, capitals for white
+fn piece_char(piece: Piece) -> char {
+    let ch = match piece.p {
+        Type::Pawn => 'p',
+        Type::Knight => 'n',
+        Type::Bishop => 'b',
+        Type::Rook => 'r',
+        Type::Queen => 'q',
+        Type::King => 'k',
+    };
+    if piece.c == Color::White {ch.to_ascii_uppercase()} else {ch}
+}
+
+// The board from white's side with ranks and files around it
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in (0..WIDTH).rev() {
+            write!(f, "{} ", y + 1)?;
+            for x in 0..WIDTH {
+                write!(f, " {}", self.b[(y*WIDTH + x) as usize].map_or('.', piece_char))?;
             }
+            writeln!(f)?;
         }
-        else {
-            break;
-        }
+        write!(f, "   a b c d e f g h")
     }
-    // Up Left
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x - i,y + i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - i, y1: y + i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+}
+
+// Algebraic name of a square, (4, 1) is e2
+fn square_name(x: i32, y: i32) -> String {
+    format!("{}{}", (b'a' + x as u8) as char, y + 1)
+}
+
+impl Board {
+    // Parses Forsyth-Edwards Notation, the move clocks may be left off and default to 0 and 1
+    fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut b = Board { c: Color::White, b: [None; 64], pieces: [0; 6], colors: [0; 2], moves: vec![], moves_made: vec![], history: vec![], result: GameResult::Ongoing, castling: 0, ep: None, halfmove: 0, fullmove: 1, hash: 0, pawn_hash: 0};
+        let mut fields = fen.split_whitespace();
+
+        // Piece placement, from rank 8 down to rank 1
+        let placement = fields.next().ok_or(FenError::MissingField("piece placement"))?;
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != WIDTH as usize {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let y = WIDTH - 1 - i as i32;
+            let mut x = 0;
+            for ch in rank.chars() {
+                if let Some(n) = ch.to_digit(10) {
+                    if n == 0 || n > 8 {
+                        return Err(FenError::RankLength(y + 1));
+                    }
+                    x += n as i32;
+                    continue;
+                }
+                let p = match ch.to_ascii_lowercase() {
+                    'p' => Type::Pawn,
+                    'n' => Type::Knight,
+                    'b' => Type::Bishop,
+                    'r' => Type::Rook,
+                    'q' => Type::Queen,
+                    'k' => Type::King,
+                    _ => return Err(FenError::Piece(ch)),
+                };
+                if x >= WIDTH {
+                    return Err(FenError::RankLength(y + 1));
+                }
+                if p == Type::Pawn && (y == 0 || y == WIDTH - 1) {
+                    return Err(FenError::PawnOnBackRank(y + 1));
+                }
+                b.write(x, y, Some(Piece{p, c: if ch.is_ascii_uppercase() {Color::White} else {Color::Black}}));
+                x += 1;
             }
-            else {
-                break;
+            if x != WIDTH {
+                return Err(FenError::RankLength(y + 1));
             }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+        }
+        for c in [Color::White, Color::Black] {
+            if b.bitboard(Type::King, c).count_ones() != 1 {
+                return Err(FenError::KingCount(c));
             }
         }
-        else {
-            break;
+
+        // Side to move
+        b.c = match fields.next().ok_or(FenError::MissingField("side to move"))? {
+            "w" => Color::White,
+            "b" => Color::Black,
+            s => return Err(FenError::SideToMove(s.to_string())),
+        };
+        if b.in_check(!b.c) {
+            return Err(FenError::OpponentInCheck);
         }
-    }
-    // Down Left
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x - i,y - i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - i, y1: y - i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+        // Castling rights, KQkq or -
+        let castling = fields.next().ok_or(FenError::MissingField("castling"))?;
+        if castling != "-" {
+            for ch in castling.chars() {
+                let right = match ch {
+                    'K' => WHITE_KINGSIDE,
+                    'Q' => WHITE_QUEENSIDE,
+                    'k' => BLACK_KINGSIDE,
+                    'q' => BLACK_QUEENSIDE,
+                    _ => return Err(FenError::Castling(castling.to_string())),
+                };
+                if b.castling & right != 0 {
+                    return Err(FenError::Castling(castling.to_string()));
+                }
+                b.castling |= right;
             }
-            else {
-                break;
+        }
+
+        // En passant square, only ever on the 3rd or 6th rank
+        let ep = fields.next().ok_or(FenError::MissingField("en passant"))?;
+        if ep != "-" {
+            let chars: Vec<char> = ep.chars().collect();
+            let expected = if b.c == Color::White {'6'} else {'3'};
+            if chars.len() != 2 || !('a'..='h').contains(&chars[0]) || chars[1] != expected {
+                return Err(FenError::EnPassant(ep.to_string()));
             }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+            let (x, y) = (chars[0] as i32 - 'a' as i32, chars[1] as i32 - '1' as i32);
+            // The pawn that just pushed two squares has to be past it, with the square it came
+            // from and the one it skipped both empty
+            let dir = b.c.sign();
+            let pawn = b.b[((y - dir)*WIDTH + x) as usize];
+            if pawn != Some(Piece{p: Type::Pawn, c: !b.c}) || b.b[(y*WIDTH + x) as usize].is_some() || b.b[((y + dir)*WIDTH + x) as usize].is_some() {
+                return Err(FenError::EnPassant(ep.to_string()));
             }
+            b.ep = Some((x, y));
         }
-        else {
-            break;
+
+        // Move clocks
+        if let Some(halfmove) = fields.next() {
+            b.halfmove = halfmove.parse().map_err(|_| FenError::Clock(halfmove.to_string()))?;
+        }
+        if let Some(fullmove) = fields.next() {
+            b.fullmove = fullmove.parse().map_err(|_| FenError::Clock(fullmove.to_string()))?;
+            if b.fullmove == 0 {
+                return Err(FenError::Clock(fullmove.to_string()));
+            }
+        }
+        if fields.next().is_some() {
+            return Err(FenError::TooManyFields);
         }
+        b.hash = b.zobrist();
+        b.pawn_hash = b.pawn_zobrist();
+        Ok(b)
     }
-    // Down Right
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x + i,y - i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + i, y1: y - i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for y in (0..WIDTH).rev() {
+            let mut empty = 0;
+            for x in 0..WIDTH {
+                let Some(piece) = self.b[(y*WIDTH + x) as usize] else {
+                    empty += 1;
+                    continue;
+                };
+                if empty != 0 {
+                    fen += &empty.to_string();
+                    empty = 0;
+                }
+                fen.push(piece_char(piece));
             }
-            else {
-                break;
+            if empty != 0 {
+                fen += &empty.to_string();
             }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+            if y != 0 {
+                fen.push('/');
             }
         }
-        else {
-            break;
+
+        fen += if self.c == Color::White {" w "} else {" b "};
+
+        if self.castling == 0 {
+            fen.push('-');
+        }
+        for (right, ch) in [(WHITE_KINGSIDE, 'K'), (WHITE_QUEENSIDE, 'Q'), (BLACK_KINGSIDE, 'k'), (BLACK_QUEENSIDE, 'q')] {
+            if self.castling & right != 0 {
+                fen.push(ch);
+            }
         }
+
+        match self.ep {
+            Some((x, y)) => fen += &format!(" {} ", square_name(x, y)),
+            None => fen += " - ",
+        }
+
+        fen += &format!("{} {}", self.halfmove, self.fullmove);
+        fen
     }
 }
 
-fn calc_rook(b:&mut Board, x: i32, y: i32, piece: Piece) {
-    // Up
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x,y + i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x, y1: y + i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
-            }
-            else {
-                break;
-            }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+// Spare move lists, one for each ply. A node swaps the moves it generated for the spare at its
+// ply, so the children generate into a list that already has room instead of allocating a new
+// one, and swaps them back with return_moves once it's done.
+fn lend_moves(b: &mut Board, spares: &mut Vec<Vec<Move>>, ply: usize) -> Vec<Move> {
+    if spares.len() <= ply {
+        spares.resize_with(ply + 1, Vec::new);
+    }
+    std::mem::replace(&mut b.moves, std::mem::take(&mut spares[ply]))
+}
+
+// Gives the node its moves back, and the spare back to its ply
+fn return_moves(b: &mut Board, spares: &mut [Vec<Move>], ply: usize, moves: Vec<Move>) {
+    spares[ply] = std::mem::replace(&mut b.moves, moves);
+}
+
+// Counts the leaf nodes of the legal move tree, used to check the move generator
+fn perft(b: &mut Board, depth: i32) -> u64 {
+    count(b, depth, &mut vec![])
+}
+
+fn count(b: &mut Board, depth: i32, spares: &mut Vec<Vec<Move>>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    b.calculate();
+    // No need to play the last moves out just to count them
+    if depth == 1 {
+        return b.moves.len() as u64;
+    }
+    let moves = lend_moves(b, spares, depth as usize);
+    let mut nodes = 0;
+    for m in &moves {
+        let undo = b.make_move(m);
+        nodes += count(b, depth - 1, spares);
+        b.unmake_move(m, undo);
+    }
+    return_moves(b, spares, depth as usize, moves);
+    nodes
+}
+
+// Perft split up by root move, handy for finding which move a count goes wrong under
+fn divide(b: &mut Board, depth: i32) -> Vec<(Move, u64)> {
+    b.calculate();
+    let moves = std::mem::take(&mut b.moves);
+    let mut counts = vec![];
+    for m in &moves {
+        let undo = b.make_move(m);
+        counts.push((*m, perft(b, depth - 1)));
+        b.unmake_move(m, undo);
+    }
+    counts
+}
+
+// Tells the search threads when to give up, either when asked to or when time runs out
+#[derive(Clone)]
+struct Stop {
+    flag: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Stop {
+    fn new(deadline: Option<Instant>) -> Stop {
+        Stop { flag: Arc::new(AtomicBool::new(false)), deadline }
+    }
+
+    fn stop(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    fn stopped(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+// How a search runs. Each part of the selective search can be switched off, so its strength
+// can be measured by playing with it against without it.
+#[derive(Clone, Copy, Debug)]
+struct Options {
+    threads: usize,
+    null_move: bool,
+    late_move_reductions: bool,
+    reverse_futility: bool,
+    futility: bool,
+    check_extensions: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { threads: 1, null_move: true, late_move_reductions: true, reverse_futility: true, futility: true, check_extensions: true }
+    }
+}
+
+// What one search thread carries from node to node
+struct Worker {
+    stop: Stop,
+    tt: Arc<TT>,
+    options: Options,
+    // The last two quiet moves that caused a cutoff at each ply, as move_code
+    killers: [[u16; 2]; MAX_PLY],
+    // How well each quiet move has done, indexed by Color::index, from square and to square
+    history: Box<[[[i32; 64]; 64]; 2]>,
+    // Triangular PV table, pv[ply] is the best line found from the node at ply
+    pv: Vec<Vec<Move>>,
+    // Spare move lists for lend_moves, indexed by ply
+    moves: Vec<Vec<Move>>,
+    // Only ever added to by this worker, shared so the nodes of every thread can be totalled while they run
+    nodes: Arc<AtomicU64>,
+}
+
+impl Worker {
+    fn new(stop: &Stop, tt: &Arc<TT>, options: Options) -> Worker {
+        Worker { stop: stop.clone(), tt: Arc::clone(tt), options, killers: [[0; 2]; MAX_PLY], history: Box::new([[[0; 64]; 64]; 2]), pv: vec![vec![]; MAX_PLY + 1], moves: vec![], nodes: Arc::new(AtomicU64::new(0)) }
+    }
+
+    // m raised alpha at ply, so the line from here is m followed by the line it led to
+    fn update_pv(&mut self, m: &Move, ply: i32) {
+        let ply = ply as usize;
+        if ply >= MAX_PLY {
+            return;
+        }
+        let (line, rest) = self.pv.split_at_mut(ply + 1);
+        let line = &mut line[ply];
+        line.clear();
+        line.push(*m);
+        line.extend_from_slice(&rest[0]);
+    }
+
+    // A quiet move refuted the opponent's last move, so it gets tried early at this ply and,
+    // by how deep the refutation went, earlier everywhere else too
+    fn cutoff(&mut self, m: &Move, depth: i32, ply: i32) {
+        let code = move_code(m);
+        if let Some(killers) = self.killers.get_mut(ply as usize) {
+            if killers[0] != code {
+                killers[1] = killers[0];
+                killers[0] = code;
             }
         }
-        else {
-            break;
+        let history = &mut self.history[m.p0.c.index()];
+        let entry = &mut history[(m.y0*WIDTH + m.x0) as usize][(m.y1*WIDTH + m.x1) as usize];
+        *entry += depth*depth;
+        // Keep well below the killers, halving everything keeps the order between moves
+        if *entry > HISTORY_MAX {
+            history.iter_mut().flatten().for_each(|h| *h /= 2);
         }
     }
-    // Left
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x - i,y) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x - i, y1: y, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+
+    // Hash move first, then captures and promotions by MVV-LVA, then the killers, then the
+    // rest of the quiet moves by history
+    fn order(&self, moves: &mut [Move], hash_move: u16, ply: i32) {
+        let killers = self.killers.get(ply as usize).copied().unwrap_or_default();
+        moves.sort_unstable_by_key(|m| {
+            let code = move_code(m);
+            let score = if code == hash_move {
+                1 << 30
             }
-            else {
-                break;
+            else if m.capture || m.promotion.is_some() {
+                (1 << 29) + mvv_lva(m)
             }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+            else if code == killers[0] {
+                (1 << 28) + 1
             }
+            else if code == killers[1] {
+                1 << 28
+            }
+            else {
+                self.history[m.p0.c.index()][(m.y0*WIDTH + m.x0) as usize][(m.y1*WIDTH + m.x1) as usize]
+            };
+            -score
+        });
+    }
+}
+
+// ply is the distance from the root, so that quicker mates score higher.
+// null says whether passing the turn may be tried, it isn't right after a pass.
+fn negamax(b: &mut Board, depth: i32, ply: i32, mut alpha: i32, beta: i32, w: &mut Worker, null: bool) -> i32 {
+    // The result gets thrown away anyways
+    if w.stop.stopped() {
+        return 0;
+    }
+    // Nodes that return before any move raises alpha have no line
+    if let Some(line) = w.pv.get_mut(ply as usize) {
+        line.clear();
+    }
+    // Inside the search a position coming up a second time is already a draw, whoever could
+    // avoid the repetition would have done so the first time
+    if ply > 0 && (b.repetitions() > 0 || b.insufficient_material()) {
+        return 0;
+    }
+    let in_check = b.in_check(b.c);
+    // The fifty-move rule, unless the move that got there was mate
+    if ply > 0 && b.halfmove >= 100 {
+        if !in_check {
+            return 0;
         }
-        else {
-            break;
-        }
+        b.calculate();
+        return if b.moves.is_empty() {-MATE + ply} else {0};
+    }
+    // Endless checks could keep extending, give up on the line somewhere
+    if ply as usize >= MAX_PLY {
+        return b.c.sign() * b.evaluate();
     }
-    // Down
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x,y - i) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x, y1: y - i, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
+    // Check extension, a check gets searched a ply deeper so it's never cut off right before the mate
+    let depth = if in_check && w.options.check_extensions {depth + 1} else {depth};
+    if depth == 0 {
+        return quiesce(b, ply, alpha, beta, w);
+    }
+    w.nodes.fetch_add(1, Ordering::Relaxed);
+    // A deep enough result from earlier can answer straight away, otherwise its move goes first
+    let mut hash_move = 0;
+    if let Some(entry) = w.tt.probe(b.hash) {
+        let score = from_tt(entry.score, ply);
+        // The root always gets searched so that there's a best move and a line to show
+        if entry.depth >= depth && ply > 0 {
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => (),
             }
-            else {
-                break;
+        }
+        hash_move = entry.best;
+    }
+
+    // Only a null window node can be pruned on a guess, a wider window is following the best line
+    let pv_node = beta - alpha > NULL_WINDOW;
+    let eval = b.c.sign() * b.evaluate();
+    if !pv_node && !in_check {
+        // Reverse futility pruning: so far above beta this close to the leaves that nothing
+        // the opponent has time for brings the score back down
+        if w.options.reverse_futility && depth <= FUTILITY_DEPTH && eval - FUTILITY_MARGIN*depth >= beta {
+            return eval;
+        }
+        // Null move pruning: if passing still leaves us above beta after a reduced search, a
+        // real move would be too. Not when only pawns are left since passing could be the only
+        // good move there (zugzwang), and never two passes in a row.
+        let pieces = b.colors[b.c.index()] & !b.pieces[Type::Pawn as usize] & !b.pieces[Type::King as usize];
+        if w.options.null_move && null && depth >= NULL_MOVE_DEPTH && eval >= beta && pieces != 0 {
+            let undo = b.make_null_move();
+            let score = -negamax(b, (depth - 1 - NULL_MOVE_REDUCTION - depth/6).max(0), ply + 1, -beta, -beta + NULL_WINDOW, w, false);
+            b.unmake_null_move(undo);
+            if score >= beta {
+                // A mate found after passing isn't real
+                return if score > MATE - 1000 {beta} else {score};
             }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
+        }
+    }
+    // Futility pruning: too far below alpha this close to the leaves for a quiet move to help
+    let futile = w.options.futility && !pv_node && !in_check && depth <= FUTILITY_DEPTH && eval + FUTILITY_MARGIN*depth <= alpha;
+
+    b.calculate();
+    match b.result {
+        GameResult::Checkmate(_) => return -MATE + ply,
+        GameResult::Stalemate => return 0,
+        // Draws only end the game at the root, which has to come up with a move anyways
+        GameResult::Draw(_) | GameResult::Ongoing => (),
+    }
+    let mut moves = lend_moves(b, &mut w.moves, ply as usize);
+    w.order(&mut moves, hash_move, ply);
+    let alpha_start = alpha;
+    let mut value = -INFINITY;
+    let mut best = 0;
+    for (i, m) in moves.iter().enumerate() {
+        let undo = b.make_move(m);
+        let quiet = !m.capture && m.promotion.is_none();
+        let gives_check = b.in_check(b.c);
+        if futile && i > 0 && quiet && !gives_check {
+            b.unmake_move(m, undo);
+            continue;
+        }
+        let mut score = -INFINITY;
+        // Late move reductions: quiet moves this far down the ordering rarely turn out best, so they
+        // get a shallower search first and only the full depth if that beats alpha
+        let reduce = w.options.late_move_reductions && i >= LMR_MOVES && depth >= LMR_DEPTH && quiet && !in_check && !gives_check;
+        if reduce {
+            let reduction = if i >= 2*LMR_MOVES {2} else {1};
+            score = -negamax(b, depth - 1 - reduction, ply + 1, -alpha - NULL_WINDOW, -alpha, w, true);
+        }
+        // Principal variation search: with good ordering the first move is the best, so the rest only
+        // get a null window around alpha to prove they're worse, and a full search if they aren't
+        if i > 0 && (!reduce || score > alpha) {
+            score = -negamax(b, depth - 1, ply + 1, -alpha - NULL_WINDOW, -alpha, w, true);
+        }
+        if i == 0 || (score > alpha && score < beta) {
+            score = -negamax(b, depth - 1, ply + 1, -beta, -alpha, w, true);
+        }
+        b.unmake_move(m, undo);
+        if score > value {
+            value = score;
+            best = move_code(m);
+        }
+        if score > alpha {
+            w.update_pv(m, ply);
+        }
+        alpha = alpha.max(value);
+        if alpha >= beta {
+            if !m.capture && m.promotion.is_none() {
+                w.cutoff(m, depth, ply);
             }
+            break;
+        }  
+    }
+    return_moves(b, &mut w.moves, ply as usize, moves);
+    if !w.stop.stopped() {
+        let bound = if value <= alpha_start {Bound::Upper} else if value >= beta {Bound::Lower} else {Bound::Exact};
+        w.tt.store(b.hash, Entry { depth, bound, score: to_tt(value, ply), best });
+    }
+    value
+}
+
+// Most valuable victim, least valuable attacker: taking a queen with a pawn looks best,
+// taking a pawn with a queen worst. En passant and promotions count as taking a pawn.
+fn mvv_lva(m: &Move) -> i32 {
+    let victim = m.p1.map_or(Type::Pawn, |p| p.p) as i32;
+    victim*8 - m.p0.p as i32
+}
+
+// Keeps searching captures past the depth limit until the position is quiet, so the evaluation
+// never gets taken in the middle of an exchange. The side to move can always stand pat on the
+// evaluation instead of capturing, except in check where every evasion gets searched.
+fn quiesce(b: &mut Board, ply: i32, mut alpha: i32, beta: i32, w: &mut Worker) -> i32 {
+    if w.stop.stopped() {
+        return 0;
+    }
+    w.nodes.fetch_add(1, Ordering::Relaxed);
+    let mut value = -INFINITY;
+    if b.in_check(b.c) {
+        b.calculate();
+        if let GameResult::Checkmate(_) = b.result {
+            return -MATE + ply;
         }
-        else {
+    }
+    else {
+        value = b.c.sign() * b.evaluate();
+        if value >= beta {
+            return value;
+        }
+        alpha = alpha.max(value);
+        b.calculate_captures();
+    }
+    let mut moves = lend_moves(b, &mut w.moves, ply as usize);
+    // Biggest victims first, so the cutoffs come before the tree of pointless captures grows
+    moves.sort_by_key(|m| -mvv_lva(m));
+    for m in &moves {
+        let undo = b.make_move(m);
+        value = value.max(-quiesce(b, ply + 1, -beta, -alpha, w));
+        b.unmake_move(m, undo);
+        alpha = alpha.max(value);
+        if alpha >= beta {
             break;
         }
     }
-    // Right
-    for i in 1..8 {
-        if let Some(piece_dest) = b.read(x + i,y) {
-            if piece_dest.c != piece.c {
-                b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + i, y1: y, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
-            }
-            else {
-                break;
-            }
-            // Don't go past a piece
-            if piece_dest.c as i32 == piece.c as i32 * -1 {
-                break;
-            }
+    return_moves(b, &mut w.moves, ply as usize, moves);
+    value
+}
+
+// What a search to some depth came up with
+#[derive(Clone, Debug)]
+struct SearchResult {
+    best: Move,
+    score: i32,
+    // The moves both sides are expected to play, starting with best
+    pv: Vec<Move>,
+    // Positions visited along the way, summed over every thread
+    nodes: u64,
+}
+
+// One iteration of iterative deepening, the root searched to depth within the window alpha to beta.
+// The board must have had calculate called on it and have at least one legal move. nodes is left
+// for the caller. A score outside the window is only a bound and comes with a meaningless line.
+fn search(b: &mut Board, depth: i32, alpha: i32, beta: i32, w: &mut Worker) -> SearchResult {
+    let score = negamax(b, depth, 0, alpha, beta, w, true);
+    let mut pv = w.pv[0].clone();
+    if pv.is_empty() {
+        pv.push(b.moves[0]);
+    }
+    extend_pv(b, &mut pv, depth, &w.tt);
+    SearchResult { best: pv[0], score, pv, nodes: 0 }
+}
+
+// Table cutoffs leave the line short, so it gets followed on through the table's best moves
+// for as long as they're legal and the line isn't longer than the search went
+fn extend_pv(b: &Board, pv: &mut Vec<Move>, depth: i32, tt: &TT) {
+    let mut b = b.clone();
+    for m in pv.iter() {
+        b.make_move(m);
+    }
+    while (pv.len() as i32) < depth {
+        let Some(entry) = tt.probe(b.hash) else {
+            break;
+        };
+        b.calculate();
+        let Some(m) = b.moves.iter().find(|m| move_code(m) == entry.best).copied() else {
+            break;
+        };
+        b.make_move(&m);
+        pv.push(m);
+    }
+}
+
+impl SearchResult {
+    // The PV as UCI moves separated by spaces
+    fn line(&self) -> String {
+        self.pv.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+// Searches the root to depth in a narrow window around the previous iteration's score, which the
+// score usually doesn't stray far from, so more of the tree gets cut off. When the score does fall
+// outside, that side of the window gets widened, twice as far each time, and searched again.
+fn aspiration(b: &mut Board, depth: i32, previous: i32, w: &mut Worker) -> SearchResult {
+    // Too shallow for the previous score to say much, and mates jump around too far
+    if depth < ASPIRATION_DEPTH || previous.abs() > MATE - 1000 {
+        return search(b, depth, -INFINITY, INFINITY, w);
+    }
+    let mut delta = ASPIRATION_WINDOW;
+    let (mut alpha, mut beta) = (previous - delta, previous + delta);
+    loop {
+        let result = search(b, depth, alpha, beta, w);
+        if w.stop.stopped() {
+            return result;
+        }
+        if result.score <= alpha {
+            alpha = (alpha - delta).max(-INFINITY);
+        }
+        else if result.score >= beta {
+            beta = (beta + delta).min(INFINITY);
         }
         else {
-            break;
+            return result;
         }
+        delta *= 2;
     }
 }
 
-fn calc_king(b:&mut Board, x: i32, y: i32, piece: Piece) {
-    for i in -1..=1 {
-        for j in -1..=1 {
-            if let Some(piece_dest) = b.read(x + i, y + j) {
-                if piece_dest.c != piece.c {
-                    b.moves.push(Move{p0: piece, x0: x, y0: y, p1: piece_dest, x1: x + i, y1: y + j, capture: piece_dest.c != NONE, promotion: false, enpassant: false, castle: false});
-                } 
+// Iterative deepening: searches depth 1, 2, 3 and so on up to depth, each iteration filling the
+// table that orders the next one. Only completed iterations count, an iteration cut short by stop
+// is thrown away. A new iteration isn't started once the soft limit has passed since it would
+// most likely get stopped anyways. report is called with every completed iteration.
+//
+// Lazy SMP: threads - 1 helpers run the same iterative deepening on their own alongside, with
+// every other one a ply deeper so they don't all move in step. They only share the table, which
+// is what makes them useful, they fill it with results the main thread then cuts off on. Only
+// the main thread's iterations get reported, its nodes count every thread's.
+fn think(b: &Board, depth: i32, soft: Option<Instant>, stop: &Stop, tt: &Arc<TT>, options: Options, mut report: impl FnMut(i32, &SearchResult)) -> SearchResult {
+    // Helpers stop with the main thread, whether it ran out of depth or got stopped
+    let helpers_stop = Stop::new(stop.deadline);
+    let mut counters = vec![];
+    let mut helpers = vec![];
+    for i in 1..options.threads {
+        let mut b = b.clone();
+        let mut w = Worker::new(&helpers_stop, tt, options);
+        counters.push(Arc::clone(&w.nodes));
+        let extra = (i % 2) as i32;
+        helpers.push(thread::spawn(move || {
+            for d in 1..=depth {
+                negamax(&mut b, d + extra, 0, -INFINITY, INFINITY, &mut w, true);
+                if w.stop.stopped() {
+                    break;
+                }
             }
+        }));
+    }
+
+    let mut w = Worker::new(stop, tt, options);
+    counters.push(Arc::clone(&w.nodes));
+    let mut b = b.clone();
+    let mut best = SearchResult { best: b.moves[0], score: -INFINITY, pv: vec![b.moves[0]], nodes: 0 };
+    for d in 1..=depth {
+        // The first iteration always gets to finish whatever the limits, so that even with no
+        // time at all the move played has been searched rather than being whichever came first
+        let result = if d == 1 {
+            let stop = std::mem::replace(&mut w.stop, Stop::new(None));
+            let result = aspiration(&mut b, d, best.score, &mut w);
+            w.stop = stop;
+            result
+        }
+        else {
+            aspiration(&mut b, d, best.score, &mut w)
+        };
+        if d > 1 && stop.stopped() {
+            break;
+        }
+        best = SearchResult { nodes: counters.iter().map(|n| n.load(Ordering::Relaxed)).sum(), ..result };
+        report(d, &best);
+        // A mate this close has been seen in full, searching deeper can't change it
+        if MATE - best.score.abs() <= d {
+            break;
+        }
+        if soft.is_some_and(|s| Instant::now() >= s) {
+            break;
         }
     }
+
+    helpers_stop.stop();
+    for helper in helpers {
+        helper.join().unwrap();
+    }
+    best.nodes = counters.iter().map(|n| n.load(Ordering::Relaxed)).sum();
+    best
 }
 
+// Reads the position for a command line command, the start position if no FEN is given
+fn cli_board(fen: &[String]) -> Board {
+    if fen.is_empty() {
+        return setup();
+    }
+    match Board::from_fen(&fen.join(" ")) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("Invalid FEN: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
 
-trait Calculate {
-    fn calculate(&mut self);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // Without a command talk UCI, that's how GUIs start engines
+    if args.is_empty() {
+        uci::run();
+        return;
+    }
+
+    match args[0].as_str() {
+        "uci" => uci::run(),
+        "search" => {
+            // search [depth <plies>] [movetime <ms>] [threads <n>] [fen]
+            let mut depth = None;
+            let mut movetime = None;
+            let mut options = Options::default();
+            let mut rest = &args[1..];
+            while rest.len() >= 2 && ["depth", "movetime", "threads"].contains(&rest[0].as_str()) {
+                let Some(value) = rest[1].parse::<u64>().ok().filter(|v| *v >= 1) else {
+                    eprintln!("Invalid {} '{}'", rest[0], rest[1]);
+                    std::process::exit(1);
+                };
+                match rest[0].as_str() {
+                    "depth" => depth = Some(value as i32),
+                    "threads" => options.threads = value as usize,
+                    _ => movetime = Some(value),
+                }
+                rest = &rest[2..];
+            }
+            // Time decides when to stop unless a depth was given too
+            let depth = depth.unwrap_or(if movetime.is_some() {MAX_DEPTH} else {SEARCH_DEPTH});
+
+            // Create Log File
+            // let file_name = "log.txt";
+            // let mut file = OpenOptions::new()
+            //     .read(true)
+            //     .write(true)
+            //     .create(true)
+            //     .append(false)
+            //     .open(file_name).unwrap();
+
+            // Create Board, from the FEN given on the command line if there is one
+            let mut b = cli_board(rest);
+            println!("Position: {}", b.to_fen());
+
+            // Calculate moves for the board
+            b.calculate();
+            if b.result != GameResult::Ongoing {
+                println!("Game over: {:?}", b.result);
+                return;
+            }
+
+            println!("Heuristic Score: {}", b.evaluate());
+
+            // Bar cause I'm a lunatic
+            let bar = ProgressBar::new(depth as u64);
+            bar.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed}] [{wide_bar:.cyan/blue}] [{pos}/{len} Depth]")
+            .unwrap()
+            .progress_chars("#>-"));
+            bar.tick();
+
+            let start = Instant::now();
+            let deadline = movetime.map(|ms| start + Duration::from_millis(ms));
+            let result = think(&b, depth, deadline, &Stop::new(deadline), &Arc::new(TT::new(tt::DEFAULT_MB)), options, |d, r| {
+                bar.println(format!("Depth {}: {} with a value of {} ({} nodes, {} ms)", d, r.line(), r.score, r.nodes, start.elapsed().as_millis()));
+                bar.inc(1);
+            });
+            bar.finish_and_clear();
+            println!("Best Move is:");
+            println!("{:?} {} with a value of {}", result.best.p0, result.best, result.score);
+            println!("Principal variation: {}", result.line());
+        },
+        "bench" => {
+            let Some(depth) = args.get(1).map_or(Some(BENCH_DEPTH), |d| d.parse::<i32>().ok().filter(|d| *d >= 1)) else {
+                eprintln!("Usage: bench [depth] [threads]");
+                std::process::exit(1);
+            };
+            let Some(threads) = args.get(2).map_or(Some(1), |t| t.parse::<usize>().ok().filter(|t| *t >= 1)) else {
+                eprintln!("Usage: bench [depth] [threads]");
+                std::process::exit(1);
+            };
+            // Each position starts from an empty table so the counts don't depend on the order
+            let tt = Arc::new(TT::new(tt::DEFAULT_MB));
+            let start = Instant::now();
+            let mut total = 0;
+            for fen in BENCH {
+                tt.clear();
+                let mut b = Board::from_fen(fen).unwrap();
+                b.calculate();
+                let result = think(&b, depth, None, &Stop::new(None), &tt, Options { threads, ..Options::default() }, |_, _| ());
+                println!("{}: {} nodes, {} {}", fen, result.nodes, result.best, result.score);
+                total += result.nodes;
+            }
+            let ms = start.elapsed().as_millis().max(1);
+            println!();
+            println!("Nodes: {}", total);
+            println!("Time: {} ms", ms);
+            println!("Nps: {}", total as u128*1000/ms);
+        },
+        "eval" => {
+            // eval [fen]
+            let b = cli_board(&args[1..]);
+            println!("{}", b);
+            println!("Fen: {}", b.to_fen());
+            println!();
+            println!("{}", b.breakdown());
+        },
+        "perft" | "divide" => {
+            let Some(depth) = args.get(1).and_then(|d| d.parse::<i32>().ok()).filter(|d| *d >= 1) else {
+                eprintln!("Usage: {} <depth> [fen]", args[0]);
+                std::process::exit(1);
+            };
+            let mut b = cli_board(&args[2..]);
+            let start = Instant::now();
+            let nodes = if args[0] == "divide" {
+                let counts = divide(&mut b, depth);
+                for (m, n) in &counts {
+                    println!("{}: {}", m, n);
+                }
+                println!();
+                counts.iter().map(|(_, n)| n).sum()
+            }
+            else {
+                perft(&mut b, depth)
+            };
+            println!("Nodes: {}", nodes);
+            println!("Time: {} ms", start.elapsed().as_millis());
+        },
+        command => {
+            eprintln!("Unknown command '{}', expected one of: uci, search [depth <plies>] [movetime <ms>] [threads <n>] [fen], bench [depth] [threads], eval [fen], perft <depth> [fen], divide <depth> [fen]", command);
+            std::process::exit(1);
+        },
+    }
 }
-impl Calculate for Board {
-    fn calculate(&mut self) {
-        self.moves = vec![];
-        for i in 0..WIDTH*WIDTH {
-            let piece = self.b[i as usize];
-            if piece.c == self.c {
-                let x = i%WIDTH;
-                let y = i/WIDTH;
-                match piece.p {
-                    Type::None => (),
-                    Type::Pawn => {
-                        calc_pawn(self, x, y, piece);
-                    },
-                    Type::Knight => {
-                        calc_knight(self, x, y, piece);
-                    },
-                    Type::Bishop => {
-                        calc_bishop(self, x, y, piece);
-                    },
-                    Type::Rook => {
-                        calc_rook(self, x, y, piece);
-                    },
-                    Type::Queen => {
-                        calc_bishop(self, x, y, piece);
-                        calc_rook(self, x, y, piece);
-                    },
-                    Type::King => {
-                        calc_king(self, x, y, piece)
-                    },
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks perft at depth 1, 2, 3 and so on against the expected counts
+    fn check_perft(fen: &str, expected: &[u64]) {
+        let mut b = Board::from_fen(fen).unwrap();
+        for (i, nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&mut b, i as i32 + 1), *nodes, "depth {} of {}", i + 1, fen);
+        }
+    }
+
+    #[test]
+    fn perft_start_position() {
+        check_perft(START_FEN, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        check_perft(KIWIPETE, &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn perft_position_3() {
+        check_perft(POSITION_3, &[14, 191, 2812, 43238, 674624]);
+    }
+
+    #[test]
+    fn perft_position_4() {
+        check_perft(POSITION_4, &[6, 264, 9467]);
+    }
+
+    #[test]
+    fn perft_position_5() {
+        check_perft(POSITION_5, &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn perft_position_6() {
+        check_perft(POSITION_6, &[46, 2079, 89890]);
+    }
+
+    #[test]
+    fn pawns_promote_to_every_piece() {
+        let mut b = Board::from_fen("8/P6k/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        b.calculate();
+        let mut promotions: Vec<String> = b.moves.iter().filter(|m| m.promotion.is_some()).map(|m| m.to_string()).collect();
+        promotions.sort();
+        assert_eq!(promotions, ["a7a8b", "a7a8n", "a7a8q", "a7a8r"]);
+
+        let m = *b.moves.iter().find(|m| m.to_string() == "a7a8n").unwrap();
+        assert_eq!(domove(&b, &m).b[56], Some(Piece{p: Type::Knight, c: Color::White}));
+    }
+
+    // Plays a move given in UCI notation, panicking if it isn't legal
+    fn play(b: &Board, uci: &str) -> Board {
+        let mut b = b.clone();
+        b.calculate();
+        let m = *b.moves.iter().find(|m| m.to_string() == uci).unwrap_or_else(|| panic!("{} is not legal", uci));
+        domove(&b, &m)
+    }
+
+    #[test]
+    fn en_passant_removes_the_captured_pawn() {
+        let mut b = setup();
+        for m in ["e2e4", "a7a6", "e4e5", "d7d5"] {
+            b = play(&b, m);
+        }
+        assert_eq!(b.ep, Some((3, 5)));
+        b = play(&b, "e5d6");
+        assert_eq!(b.to_fen(), "rnbqkbnr/1pp1pppp/p2P4/8/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3");
+    }
+
+    #[test]
+    fn en_passant_only_right_after_the_double_push() {
+        let mut b = setup();
+        for m in ["e2e4", "a7a6", "e4e5", "d7d5", "h2h3", "h7h6"] {
+            b = play(&b, m);
+        }
+        assert_eq!(b.ep, None);
+        b.calculate();
+        assert!(b.moves.iter().all(|m| !m.enpassant));
+    }
+
+    #[test]
+    fn en_passant_from_fen() {
+        let mut b = Board::from_fen("4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 2").unwrap();
+        b.calculate();
+        assert!(b.moves.iter().any(|m| m.enpassant && m.to_string() == "d5c6"));
+        // The same position without the en passant square has no en passant capture
+        let mut b = Board::from_fen("4k3/8/8/2pP4/8/8/8/4K3 w - - 0 2").unwrap();
+        b.calculate();
+        assert!(b.moves.iter().all(|m| !m.enpassant));
+    }
+
+    #[test]
+    fn malformed_fens_are_rejected() {
+        for (fen, error) in [
+            ("", FenError::MissingField("piece placement")),
+            ("4k3/8/8/8/8/8/8/4K3", FenError::MissingField("side to move")),
+            ("4k3/8/8/8/8/8/8/4K3 w", FenError::MissingField("castling")),
+            ("4k3/8/8/8/8/8/8/4K3 w -", FenError::MissingField("en passant")),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1 extra", FenError::TooManyFields),
+            ("4k3/8/8/8/8/8/4K3 w - - 0 1", FenError::RankCount(7)),
+            ("4k3/8/8/8/8/8/8/4K4 w - - 0 1", FenError::RankLength(1)),
+            ("4k3/8/8/8/8/8/8/4K2 w - - 0 1", FenError::RankLength(1)),
+            ("4k3/8/8/8/8/8/8/4K0 w - - 0 1", FenError::RankLength(1)),
+            ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::Piece('X')),
+            ("4k3/8/8/8/8/8/8/8 w - - 0 1", FenError::KingCount(Color::White)),
+            ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::KingCount(Color::White)),
+            ("P3k3/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank(8)),
+            ("4k3/8/8/8/8/8/8/p3K3 w - - 0 1", FenError::PawnOnBackRank(1)),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::SideToMove("x".to_string())),
+            ("4k3/p7/8/8/8/8/8/4R1K1 w - - 0 1", FenError::OpponentInCheck),
+            ("4k3/8/8/8/8/8/8/4K3 w KK - 0 1", FenError::Castling("KK".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w X - 0 1", FenError::Castling("X".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - e3 0 1", FenError::EnPassant("e3".to_string())),
+            // No pawn that could have just pushed past e6, past it but with the squares behind taken
+            ("4k3/8/8/3PN3/8/8/8/4K3 w - e6 0 1", FenError::EnPassant("e6".to_string())),
+            ("4k3/8/8/3Pp3/8/8/8/4K3 w - d6 0 1", FenError::EnPassant("d6".to_string())),
+            ("4k3/4n3/8/3Pp3/8/8/8/4K3 w - e6 0 1", FenError::EnPassant("e6".to_string())),
+            ("4k3/8/4n3/3Pp3/8/8/8/4K3 w - e6 0 1", FenError::EnPassant("e6".to_string())),
+            ("4k3/8/8/8/3pP3/4N3/8/4K3 b - e3 0 1", FenError::EnPassant("e3".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::Clock("x".to_string())),
+        ] {
+            assert_eq!(Board::from_fen(fen).err(), Some(error), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn en_passant_cannot_expose_the_king() {
+        // Taking on e3 would take both pawns off the fourth rank and leave the king to the rook
+        let mut b = Board::from_fen("8/8/8/8/1R2Pp1k/8/8/4K3 b - e3 0 1").unwrap();
+        b.calculate();
+        assert!(b.moves.iter().all(|m| !m.enpassant));
+    }
+
+    #[test]
+    fn unmake_move_restores_the_board() {
+        // Castling, promotions, captures and en passant all show up within two plies of these
+        for fen in [KIWIPETE, POSITION_3, POSITION_4, POSITION_5, "4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 2"] {
+            let mut b = Board::from_fen(fen).unwrap();
+            b.calculate();
+            for m in b.moves.clone() {
+                let undo = b.make_move(&m);
+                b.calculate();
+                for reply in b.moves.clone() {
+                    let before = (b.to_fen(), b.pieces, b.colors, b.moves_made.clone(), b.hash);
+                    let undo = b.make_move(&reply);
+                    b.unmake_move(&reply, undo);
+                    assert_eq!((b.to_fen(), b.pieces, b.colors, b.moves_made.clone(), b.hash), before, "{} {}", m, reply);
                 }
+                b.unmake_move(&m, undo);
+                assert_eq!(b.to_fen(), fen, "{}", m);
             }
         }
     }
-}
 
-trait Evaluate {
-    fn evaluate(&self) -> f32;
-}
-impl Evaluate for Board {
-    fn evaluate(&self) -> f32 {
-        let mut sum: f32 = 0.0;
-        for i in 0..WIDTH*WIDTH {
-            sum += match &self.b[i as usize].p{
-                Type::None => 0.,
-                Type::Pawn => 1.,
-                Type::Knight => 3.05,
-                Type::Bishop => 3.33,
-                Type::Rook => 5.63,
-                Type::Queen => 9.5,
-                Type::King => 9999.,
-            } *&self.b[i as usize].c;
-        };
-        return sum;
+    // Walks the move tree checking the incremental hash against one worked out from scratch
+    fn check_hash(b: &mut Board, depth: i32) {
+        assert_eq!(b.hash, b.zobrist(), "{}", b.to_fen());
+        assert_eq!(b.pawn_hash, b.pawn_zobrist(), "{}", b.to_fen());
+        if depth == 0 {
+            return;
+        }
+        b.calculate();
+        for m in b.moves.clone() {
+            let undo = b.make_move(&m);
+            check_hash(b, depth - 1);
+            b.unmake_move(&m, undo);
+        }
     }
-}
 
-fn domove(b: &Board, mo: &Move) -> Board{
-    // Open File
-    // let file_name = "log.txt";
-    // let mut file = OpenOptions::new()
-    //     .read(true)
-    //     .write(true)
-    //     .create(false)
-    //     .append(true)
-    //     .open(file_name).unwrap();
+    #[test]
+    fn incremental_hash_matches_full_hash() {
+        for fen in [KIWIPETE, POSITION_3, POSITION_4, POSITION_5] {
+            check_hash(&mut Board::from_fen(fen).unwrap(), 3);
+        }
+    }
 
-    // Log Moves into File
-    // match write!(file, "{}", format!("{:?}{:?}{:?}\n", m.p0.p, m.x1, m.y1)) {
-    //     Ok(_) => (),
-    //     Err(_) => println!("Problem writing move {:?}", file),
-    // }
+    #[test]
+    fn hash_depends_on_position_not_move_order() {
+        let mut a = setup();
+        for m in ["g1f3", "g8f6", "b1c3", "b8c6"] {
+            a = play(&a, m);
+        }
+        let mut b = setup();
+        for m in ["b1c3", "b8c6", "g1f3", "g8f6"] {
+            b = play(&b, m);
+        }
+        assert_eq!(a.hash, b.hash);
 
-    // If Promotion for Pawn
-    let m = mo.clone();
-    let mut board = b.clone();
-    if m.promotion == true{
-        board.b[(m.y1*WIDTH + m.x1) as usize] = Piece{p: Type::Queen, c: m.p0.c};
-        board.b[(m.y0*WIDTH + m.x0) as usize] = Piece{p: Type::None, c: NONE};
+        // Same pieces but different castling rights, side to move or en passant square
+        let hash = |fen| Board::from_fen(fen).unwrap().hash;
+        assert_ne!(hash(START_FEN), hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Qkq - 0 1"));
+        assert_ne!(hash(START_FEN), hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1"));
+        assert_ne!(hash("4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 2"), hash("4k3/8/8/2pP4/8/8/8/4K3 w - - 0 2"));
     }
-    // Else normal move
-    else {
-    board.b[(m.y1*WIDTH + m.x1) as usize] = m.p0;
-    board.b[(m.y0*WIDTH + m.x0) as usize] = Piece{p: Type::None, c: NONE};
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        let counts = divide(&mut b, 2);
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|(_, n)| n).sum::<u64>(), perft(&mut b, 2));
     }
-    board.moves_made.push(m);
-    if m.p1.p == Type::King {
-        board.winner = (m.p1.c * -1.) as i32;
+
+    #[test]
+    fn transposition_table_round_trips_entries() {
+        let tt = TT::new(1);
+        let m = Move { p0: Piece { p: Type::Pawn, c: Color::White }, x0: 4, y0: 6, p1: None, x1: 4, y1: 7, capture: false, promotion: Some(Type::Queen), enpassant: false, castle: false };
+        let entry = Entry { depth: 5, bound: Bound::Lower, score: -325, best: move_code(&m) };
+        tt.store(0x1234_5678_9abc_def0, entry);
+        assert_eq!(tt.probe(0x1234_5678_9abc_def0), Some(entry));
+        // Same slot, different position
+        assert_eq!(tt.probe(0x1234_5679_9abc_def0), None);
+        tt.clear();
+        assert_eq!(tt.probe(0x1234_5678_9abc_def0), None);
     }
-    else {
-        board.winner = 0;
+
+    #[test]
+    fn search_agrees_with_a_filled_table() {
+        let tt = Arc::new(TT::new(1));
+        // Back rank mate, the score has to survive being stored at one ply and read at another
+        let mut b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        b.calculate();
+        let result = think(&b, 4, None, &Stop::new(None), &tt, Options::default(), |_, _| ());
+        assert_eq!(result.best.to_string(), "d1d8");
+        assert_eq!(result.score, MATE - 1);
+        let again = think(&b, 4, None, &Stop::new(None), &tt, Options::default(), |_, _| ());
+        assert_eq!((again.best, again.score), (result.best, result.score));
+
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        let fresh = think(&b, 3, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ()).score;
+        think(&b, 3, None, &Stop::new(None), &tt, Options::default(), |_, _| ());
+        assert_eq!(think(&b, 3, None, &Stop::new(None), &tt, Options::default(), |_, _| ()).score, fresh);
     }
-    board.c *= -1.;
-    return board;
-}
 
+    #[test]
+    fn iterative_deepening_stops_once_mate_is_seen() {
+        let mut b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        b.calculate();
+        let mut depths = vec![];
+        let result = think(&b, 10, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |d, _| depths.push(d));
+        assert_eq!(depths, vec![1]);
+        assert_eq!((result.best.to_string(), result.score), ("d1d8".to_string(), MATE - 1));
+    }
 
-fn setup() -> Board {
-    let mut b: Board = Board { c: WHITE, b: [Piece{p: Type::None, c: NONE}; 64], moves: vec![], moves_made: vec![], winner: 0};
-    // White pieces
-    b.write(0, 0, Piece{p: Type::Rook, c: WHITE});
-    b.write(1, 0, Piece{p: Type::Knight, c: WHITE});
-    b.write(2, 0, Piece{p: Type::Bishop, c: WHITE});
-    b.write(3, 0, Piece{p: Type::Queen, c: WHITE});
-    b.write(4, 0, Piece{p: Type::King, c: WHITE});
-    b.write(5, 0, Piece{p: Type::Bishop, c: WHITE});
-    b.write(6, 0, Piece{p: Type::Knight, c: WHITE});
-    b.write(7, 0, Piece{p: Type::Rook, c: WHITE});
-    b.write(0, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(1, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(2, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(3, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(4, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(5, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(6, 1, Piece{p: Type::Pawn, c: WHITE});
-    b.write(7, 1, Piece{p: Type::Pawn, c: WHITE});
-    // Black Pieces
-    b.write(0, 7, Piece{p: Type::Rook, c: BLACK});
-    b.write(1, 7, Piece{p: Type::Knight, c: BLACK});
-    b.write(2, 7, Piece{p: Type::Bishop, c: BLACK});
-    b.write(3, 7, Piece{p: Type::Queen, c: BLACK});
-    b.write(4, 7, Piece{p: Type::King, c: BLACK});
-    b.write(5, 7, Piece{p: Type::Bishop, c: BLACK});
-    b.write(6, 7, Piece{p: Type::Knight, c: BLACK});
-    b.write(7, 7, Piece{p: Type::Rook, c: BLACK});
-    b.write(0, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(1, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(2, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(3, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(4, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(5, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(6, 6, Piece{p: Type::Pawn, c: BLACK});
-    b.write(7, 6, Piece{p: Type::Pawn, c: BLACK});
-    return b
-}
-
-fn negamax(mut b: Board, depth: i32, mut alpha: f32, beta: f32) -> f32 {
-    if depth == 0 || b.winner != 0{
-        return b.c * b.evaluate();
+    #[test]
+    fn moves_are_ordered_hash_captures_killers_history() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        let mut w = Worker::new(&Stop::new(None), &Arc::new(TT::new(1)), Options::default());
+        let find = |uci: &str| *b.moves.iter().find(|m| m.to_string() == uci).unwrap();
+        // a2a3 is the killer at ply 2 but has less history than g2g3
+        w.cutoff(&find("a2a3"), 1, 2);
+        w.cutoff(&find("g2g3"), 3, 5);
+        let mut moves = b.moves.clone();
+        w.order(&mut moves, move_code(&find("d2h6")), 2);
+        let order: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+        // The hash move, then the biggest victim, then queen takes knight before pawn takes pawn
+        assert_eq!(order[..3], ["d2h6", "e2a6", "f3f6"]);
+        // The killer for this ply beats the quiet move with more history
+        let captures = moves.iter().filter(|m| m.capture).count();
+        assert_eq!(order[captures + 1..captures + 3], ["a2a3", "g2g3"]);
     }
-    b.calculate();
-    let mut value: f32 = -1. * f32::MAX;
-    for m in b.clone().moves {
-        value = value.max(-1. * negamax(domove(&b, &m), depth - 1, beta * -1., alpha * -1.));
-        alpha = alpha.max(value);
-        if alpha >= beta {
-            break;
-        }  
+
+    #[test]
+    fn principal_variation_is_a_legal_line() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        let result = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        assert_eq!(result.pv[0], result.best);
+        assert!(result.pv.len() <= 4);
+        let mut line = b.clone();
+        for m in &result.pv {
+            line.calculate();
+            assert!(line.moves.contains(m), "{} isn't legal in {}", m, line.to_fen());
+            line.make_move(m);
+        }
+        assert_eq!(result.line().split(' ').count(), result.pv.len());
     }
-    return value;
-}
 
-fn main() {
-    let depth = 6;
-    // Create Log File
-    // let file_name = "log.txt";
-    // let mut file = OpenOptions::new()
-    //     .read(true)
-    //     .write(true)
-    //     .create(true)
-    //     .append(false)
-    //     .open(file_name).unwrap();
-
-    // Create Board
-    let mut b= setup();
-
-    // Calculate moves for the board
-    b.calculate();
+    #[test]
+    fn helper_threads_find_the_same_mate() {
+        let mut b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        b.calculate();
+        let single = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        let smp = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), Options { threads: 4, ..Options::default() }, |_, _| ());
+        assert_eq!((smp.best, smp.score), (single.best, single.score));
+    }
 
-    println!("Heuristic Score: {}", b.evaluate());
+    #[test]
+    fn helper_results_in_the_table_save_the_main_thread_work() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        let main_nodes = |tt: &Arc<TT>| {
+            let mut w = Worker::new(&Stop::new(None), tt, Options::default());
+            negamax(&mut b.clone(), 4, 0, -INFINITY, INFINITY, &mut w, true);
+            w.nodes.load(Ordering::Relaxed)
+        };
+        let alone = main_nodes(&Arc::new(TT::new(1)));
+        // A helper a ply deeper fills the shared table first, as it would running alongside
+        let tt = Arc::new(TT::new(1));
+        let mut helper = Worker::new(&Stop::new(None), &tt, Options::default());
+        negamax(&mut b.clone(), 5, 0, -INFINITY, INFINITY, &mut helper, true);
+        let helped = main_nodes(&tt);
+        assert!(helped < alone, "{} {}", helped, alone);
+    }
 
-    // Immutable reference across threads
-    let arc_b = Arc::new(b.clone());
+    #[test]
+    fn aspiration_windows_agree_with_full_windows() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        // Iterative deepening to depth 6 with aspiration windows from depth 4 on, and without any
+        let deepen = |aspirate: bool| {
+            let mut w = Worker::new(&Stop::new(None), &Arc::new(TT::new(1)), Options::default());
+            let mut b = b.clone();
+            let mut result = search(&mut b, 1, -INFINITY, INFINITY, &mut w);
+            for d in 2..=6 {
+                result = if aspirate {aspiration(&mut b, d, result.score, &mut w)} else {search(&mut b, d, -INFINITY, INFINITY, &mut w)};
+            }
+            result
+        };
+        let (narrow, full) = (deepen(true), deepen(false));
+        assert_eq!((narrow.best, narrow.score), (full.best, full.score));
+    }
 
-    // List of threads that we generate
-    let mut threads: Vec<_> = vec![];
+    #[test]
+    fn aspiration_searches_again_outside_the_window() {
+        let mut b = Board::from_fen(KIWIPETE).unwrap();
+        b.calculate();
+        let fresh = || Worker::new(&Stop::new(None), &Arc::new(TT::new(1)), Options::default());
+        let full = search(&mut b, ASPIRATION_DEPTH, -INFINITY, INFINITY, &mut fresh());
+        // A guess far too high fails low and one far too low fails high, either way the window
+        // has to widen until it takes in the real score
+        for guess in [full.score + 500, full.score - 500] {
+            let result = aspiration(&mut b, ASPIRATION_DEPTH, guess, &mut fresh());
+            assert_eq!((result.best, result.score), (full.best, full.score), "{}", guess);
+        }
+    }
 
-    // Thread bar cause I'm a lunatic
-    let thread_bar = ProgressBar::new((b.clone().moves.len()) as u64);
-    thread_bar.set_style(ProgressStyle::with_template("{spinner:.green} [{wide_bar:.green/blue}] [{pos}/{len} Threads Generated]")
-    .unwrap()
-    .progress_chars("#>-"));
+    #[test]
+    fn every_switch_combination_agrees() {
+        let all = |bits: u32| Options { threads: 1, null_move: bits & 1 != 0, late_move_reductions: bits & 2 != 0, reverse_futility: bits & 4 != 0, futility: bits & 8 != 0, check_extensions: bits & 16 != 0 };
+        for fen in BENCH.iter().chain(&["6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1"]) {
+            let mut b = Board::from_fen(fen).unwrap();
+            b.calculate();
+            let expected = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+            for bits in 0..32 {
+                let result = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), all(bits), |_, _| ());
+                assert_eq!(result.best, expected.best, "{:?} {}", all(bits), fen);
+                // A mate is a mate whichever parts of the search are on
+                if expected.score.abs() > MATE - 1000 {
+                    assert_eq!(result.score, expected.score, "{:?} {}", all(bits), fen);
+                }
+            }
+        }
+    }
 
-    for i in 0..b.clone().moves.len(){
+    #[test]
+    fn null_move_is_never_tried_with_only_pawns() {
+        let nodes = |fen, null_move| {
+            let mut b = Board::from_fen(fen).unwrap();
+            b.calculate();
+            think(&b, 8, None, &Stop::new(None), &Arc::new(TT::new(1)), Options { null_move, ..Options::default() }, |_, _| ()).nodes
+        };
+        // White is well ahead, so null move would be tried all over if it were allowed
+        let pawns = "8/5k2/8/4P3/4K3/8/1P6/8 w - - 0 1";
+        assert_eq!(nodes(pawns, true), nodes(pawns, false));
+        // With a piece it is
+        let knight = "8/5k2/8/4P3/4K3/8/1P6/6N1 w - - 0 1";
+        assert_ne!(nodes(knight, true), nodes(knight, false));
+    }
 
-        // Copy our board data
-        let arc_b = Arc::clone(&arc_b);
+    #[test]
+    fn repetitions_draw_the_game() {
+        let mut b = setup();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for (i, m) in shuffle.iter().cycle().take(16).enumerate() {
+            b = play(&b, m);
+            b.calculate();
+            // Every position comes back after four more moves, the start position a third time after the eighth
+            let expected = match i {
+                0..=6 => GameResult::Ongoing,
+                15 => GameResult::Draw(Draw::FivefoldRepetition),
+                _ => GameResult::Draw(Draw::ThreefoldRepetition),
+            };
+            assert_eq!(b.result, expected, "after move {}", i + 1);
+        }
+        // A capture means nothing before it can come back
+        let mut b = Board::from_fen("4k3/8/8/8/8/3p4/8/R3K3 w - - 0 1").unwrap();
+        for m in ["a1a3", "e8f8", "a3a1", "f8e8", "a1a3", "e8f8", "a3d3"] {
+            b = play(&b, m);
+        }
+        assert_eq!(b.repetitions(), 0);
+    }
 
-        // Create a thread that does the negamax function
-        let handle = std::thread::spawn(move || {
-                let value =  negamax(domove(&arc_b, &arc_b.moves[i]), depth, -999999., 999999.);
-                return (value, arc_b.moves[i]);
-       
-        });
-        // Puts this thread into our list of threads
-        threads.push(handle);
-        thread_bar.inc(1);
-    }
-
-    let mut moves: Vec<Move> = vec![];
-    let mut values: Vec<f32> = vec![];
-
-    let bar = ProgressBar::new((b.clone().moves.len()) as u64);
-    bar.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed}] [{wide_bar:.cyan/blue}] [{pos}/{len} Moves]")
-    .unwrap()
-    .progress_chars("#>-"));
-    bar.tick();
-
-    // For each thread wait for them to finish and unwrap their tuple results
-    for thread in threads {
-        let (v, m) = thread.join().unwrap();
-        moves.push(m);
-        values.push(v);
-        // This is entirely just so the bar looks prettier and is in fact less efficient and stupid
-        bar.inc(1);
-        thread::sleep(time::Duration::from_millis(20))
-    }  
-    
-    // Find Max
-    let mut max_index = 0;
-    for i in 0..moves.len() {
-        if values[i] > values[max_index] {
-            max_index = i;
-        }
-    }
-    println!("Best Move is:");
-    println!("{:?} from ({},{}) to ({},{}) with a value of {}", moves[max_index].p0, moves[max_index].x0, moves[max_index].y0, moves[max_index].x1, moves[max_index].y1, values[max_index]);
-    
+    #[test]
+    fn move_rules_draw_the_game() {
+        let calculated = |fen| {
+            let mut b = Board::from_fen(fen).unwrap();
+            b.calculate();
+            b.result
+        };
+        assert_eq!(calculated("4k3/8/8/8/8/8/8/R3K3 w - - 99 80"), GameResult::Ongoing);
+        assert_eq!(calculated("4k3/8/8/8/8/8/8/R3K3 w - - 100 80"), GameResult::Draw(Draw::FiftyMoves));
+        assert_eq!(calculated("4k3/8/8/8/8/8/8/R3K3 w - - 150 80"), GameResult::Draw(Draw::SeventyFiveMoves));
+        // Mate on the fiftieth move still counts
+        assert_eq!(calculated("R3k3/8/4K3/8/8/8/8/8 b - - 100 80"), GameResult::Checkmate(Color::White));
+    }
+
+    #[test]
+    fn stalemate_ends_the_game_as_a_draw() {
+        // Black's king has no moves and isn't in check
+        let mut b = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        b.calculate();
+        assert_eq!(b.result, GameResult::Stalemate);
+        let mut w = Worker::new(&Stop::new(None), &Arc::new(TT::new(1)), Options::default());
+        assert_eq!(negamax(&mut b, 3, 0, -INFINITY, INFINITY, &mut w, true), 0);
+    }
+
+    #[test]
+    fn search_sees_mate_on_the_fiftieth_move() {
+        let mut b = Board::from_fen("4k3/8/4K3/8/8/8/8/R7 w - - 99 80").unwrap();
+        b.calculate();
+        let result = think(&b, 3, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        assert_eq!(result.best.to_string(), "a1a8");
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn first_iteration_finishes_without_time() {
+        let mut b = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        b.calculate();
+        let now = Instant::now();
+        let result = think(&b, MAX_DEPTH, Some(now), &Stop::new(Some(now)), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        assert_eq!(result.best.to_string(), "d1d8");
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn quiescence_sees_the_recapture() {
+        // Qxd5 wins a pawn at depth 1 unless exd5 gets looked at too
+        let mut b = Board::from_fen("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        b.calculate();
+        let result = think(&b, 1, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        assert_ne!(result.best.to_string(), "d1d5");
+        assert!(result.score > 0, "{}", result.score);
+        // Right after Qxd5 white looks a queen up, but with exd5 searched it's black who's ahead
+        let m = *b.moves.iter().find(|m| m.to_string() == "d1d5").unwrap();
+        b.make_move(&m);
+        let mut w = Worker::new(&Stop::new(None), &Arc::new(TT::new(1)), Options::default());
+        let score = quiesce(&mut b, 1, -INFINITY, INFINITY, &mut w);
+        assert!(b.evaluate() > 500 && score > 0, "{} {}", b.evaluate(), score);
+    }
+
+    #[test]
+    fn dead_positions_are_draws() {
+        let draw = |fen| Board::from_fen(fen).unwrap().insufficient_material();
+        assert!(draw("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(draw("4k3/8/8/8/8/8/8/2N1K3 w - - 0 1"));
+        assert!(draw("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        // Bishops all on dark squares
+        assert!(draw("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!draw("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!draw("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"));
+        assert!(!draw("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn search_takes_a_repetition_when_behind() {
+        // Black is well ahead but stuck, so white checks forever with Qh5, Qe8 and so on
+        let mut b = Board::from_fen("rn5k/pp1p2p1/p1p5/8/8/8/8/3Q2K1 w - - 0 1").unwrap();
+        b.calculate();
+        let result = think(&b, 4, None, &Stop::new(None), &Arc::new(TT::new(1)), Options::default(), |_, _| ());
+        assert_eq!(result.best.to_string(), "d1h5");
+        assert_eq!(result.score, 0);
+    }
+
+    #[test]
+    fn evaluation_is_symmetric() {
+        assert_eq!(setup().evaluate(), 0);
+        // Each position against itself with the colors swapped and the board flipped
+        for (fen, flipped) in [
+            (KIWIPETE, "r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1"),
+            (POSITION_4, "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1"),
+            ("4k3/8/8/3P4/8/2N5/8/4K3 w - - 0 1", "4k3/8/2n5/8/3p4/8/8/4K3 b - - 0 1"),
+        ] {
+            let score = Board::from_fen(fen).unwrap().evaluate();
+            assert_eq!(Board::from_fen(flipped).unwrap().evaluate(), -score, "{}", fen);
+        }
+        // A knight in the center beats one in the corner
+        let center = Board::from_fen("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1").unwrap().evaluate();
+        let corner = Board::from_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1").unwrap().evaluate();
+        assert!(center > corner);
+    }
+
+    #[test]
+    fn pawn_structure_is_scored() {
+        let pawns = |fen| pawns::evaluate(&Board::from_fen(fen).unwrap())[Color::White.index()];
+        // Same material each time, only the structure changes
+        let healthy = pawns("4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1");
+        let doubled = pawns("4k3/8/8/8/8/1P6/PP6/4K3 w - - 0 1");
+        let isolated = pawns("4k3/8/8/8/8/8/P1P1P3/4K3 w - - 0 1");
+        assert!(doubled.mg < healthy.mg && doubled.eg < healthy.eg);
+        assert!(isolated.mg < healthy.mg && isolated.eg < healthy.eg);
+        // A passed pawn counts for more the further up it is and less with something in front
+        let far = pawns("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1");
+        let near = pawns("4k3/8/8/8/8/1P6/8/4K3 w - - 0 1");
+        let blocked = pawns("4k3/1n6/1P6/8/8/8/8/4K3 w - - 0 1");
+        assert!(far.eg > near.eg && far.eg > blocked.eg && blocked.eg > 0);
+        // Pawns that get in each other's way aren't passed, so the two isolated pawns score the same
+        let [white, black] = pawns::evaluate(&Board::from_fen("4k3/8/8/2p5/8/1P6/8/4K3 w - - 0 1").unwrap());
+        assert_eq!(white, black);
+        // Cached and worked out again give the same answer
+        assert_eq!(pawns(KIWIPETE), pawns(KIWIPETE));
+    }
+
+    #[test]
+    fn king_safety_and_mobility_are_scored() {
+        let white = |fen| eval::Breakdown::new(&Board::from_fen(fen).unwrap()).king_safety[Color::White.index()].mg;
+        let shielded = white("6k1/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let advanced = white("6k1/8/8/8/8/6P1/5P1P/6K1 w - - 0 1");
+        let open = white("6k1/8/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert!(shielded > advanced && advanced > open);
+        // One piece near the king isn't counted, two are
+        let queen = white("6k1/8/8/8/7q/8/5PPP/6K1 w - - 0 1");
+        let queen_and_knight = white("6k1/8/8/8/5n1q/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(queen, shielded);
+        assert!(queen_and_knight < queen);
+        // Hemmed in by its own pawns the bishop can hardly move
+        let mobility = |fen| eval::Breakdown::new(&Board::from_fen(fen).unwrap()).mobility[Color::White.index()].mg;
+        assert!(mobility("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1") > mobility("4k3/8/8/8/8/8/1P1P4/2B1K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn breakdown_adds_up_to_the_evaluation() {
+        for fen in BENCH {
+            let b = Board::from_fen(fen).unwrap();
+            let breakdown = b.breakdown();
+            assert_eq!(breakdown.score(), b.evaluate(), "{}", fen);
+            let trace = breakdown.to_string();
+            for (name, _) in breakdown.terms() {
+                assert!(trace.contains(name), "{}", trace);
+            }
+        }
+        // White is a knight up, nothing else
+        let breakdown = Board::from_fen("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap().breakdown();
+        assert_eq!(breakdown.material, [eval::Score { mg: 337, eg: 281 }, eval::Score::default()]);
+        assert_eq!(breakdown.phase, 1);
+    }
 }
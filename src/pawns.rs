@@ -0,0 +1,135 @@
+// Pawn structure, scored for each side on its own like the other evaluation terms. It only
+// depends on where the pawns are, which changes far less often than anything else, so the work
+// gets cached in a table keyed by the pawn hash.
+use std::cell::RefCell;
+
+use crate::bitboard::{FILE_A, FILE_H, PAWN_ATTACKS};
+use crate::eval::Score;
+use crate::{Board, Color, Type};
+
+// Penalties per pawn
+const DOUBLED: Score = Score { mg: -10, eg: -20 };
+const ISOLATED: Score = Score { mg: -10, eg: -15 };
+const BACKWARD: Score = Score { mg: -8, eg: -10 };
+// Passed pawn bonus by rank counted from the pawn's own side, a pawn on its last rank has promoted
+const PASSED_MG: [i32; 8] = [0, 5, 10, 15, 30, 50, 80, 0];
+const PASSED_EG: [i32; 8] = [0, 10, 15, 25, 45, 75, 120, 0];
+
+// Entries in each thread's pawn table, a power of two
+const TABLE_SIZE: usize = 1 << 14;
+
+// What the pawns on their own are worth. The passed pawns are kept rather than scored since
+// whether something is in their way depends on the other pieces too.
+#[derive(Clone, Copy, Default)]
+struct Structure {
+    key: u64,
+    // Indexed by Color::index
+    score: [Score; 2],
+    // Passed pawns of both colors
+    passed: u64,
+}
+
+thread_local! {
+    // Every search thread gets its own so they never wait on each other. A position without pawns
+    // has a pawn hash of 0, which is what empty entries hold, and it scores 0 as well.
+    static TABLE: RefCell<Vec<Structure>> = RefCell::new(vec![Structure::default(); TABLE_SIZE]);
+}
+
+// Ranks strictly in front of rank y for color c
+pub fn ahead(c: Color, y: u32) -> u64 {
+    match c {
+        Color::White => (!0u64).checked_shl(8*(y + 1)).unwrap_or(0),
+        Color::Black => (1u64 << (8*y)) - 1,
+    }
+}
+
+// Files either side of file x
+fn adjacent_files(x: u32) -> u64 {
+    let file = FILE_A << x;
+    ((file << 1) & !FILE_A) | ((file >> 1) & !FILE_H)
+}
+
+fn structure(b: &Board) -> Structure {
+    let mut s = Structure { key: b.pawn_hash, ..Structure::default() };
+    for c in [Color::White, Color::Black] {
+        let own = b.bitboard(Type::Pawn, c);
+        let enemy = b.bitboard(Type::Pawn, !c);
+        let mut pawns = own;
+        while pawns != 0 {
+            let sq = pawns.trailing_zeros();
+            pawns &= pawns - 1;
+            let (x, y) = (sq % 8, sq / 8);
+            let front = ahead(c, y);
+            let adjacent = own & adjacent_files(x);
+            let score = &mut s.score[c.index()];
+            // Another pawn of ours in front on the same file
+            let doubled = own & (FILE_A << x) & front != 0;
+            if doubled {
+                *score += DOUBLED;
+            }
+            if adjacent == 0 {
+                *score += ISOLATED;
+            }
+            // No pawn of ours beside or behind it to ever guard it, and it can't step up without
+            // being taken
+            else if adjacent & !front == 0 {
+                let stop = (sq as i32 + 8*c.sign()) as usize;
+                if PAWN_ATTACKS[c.index()][stop] & enemy != 0 {
+                    *score += BACKWARD;
+                }
+            }
+            // Nothing of theirs in front of it on its file or the files beside
+            if !doubled && enemy & front & ((FILE_A << x) | adjacent_files(x)) == 0 {
+                s.passed |= 1 << sq;
+            }
+        }
+    }
+    s
+}
+
+// The pawn structure from the table, worked out and stored if it isn't there
+fn probe(b: &Board) -> Structure {
+    TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        let entry = &mut table[b.pawn_hash as usize & (TABLE_SIZE - 1)];
+        if entry.key != b.pawn_hash {
+            *entry = structure(b);
+        }
+        *entry
+    })
+}
+
+// Score of each side's pawns. A passed pawn is worth more the further it's got, and only half
+// as much while something stands in its way.
+pub fn evaluate(b: &Board) -> [Score; 2] {
+    let s = probe(b);
+    let mut score = s.score;
+    let mut passed = s.passed;
+    while passed != 0 {
+        let sq = passed.trailing_zeros();
+        passed &= passed - 1;
+        let c = if b.colors[Color::White.index()] & (1 << sq) != 0 {Color::White} else {Color::Black};
+        let (x, y) = (sq % 8, sq / 8);
+        let rank = if c == Color::White {y} else {7 - y} as usize;
+        let blocked = b.occupied() & (FILE_A << x) & ahead(c, y) != 0;
+        let divisor = if blocked {2} else {1};
+        score[c.index()] += Score { mg: PASSED_MG[rank]/divisor, eg: PASSED_EG[rank]/divisor };
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backward_pawns_are_penalised() {
+        let white = |fen| structure(&Board::from_fen(fen).unwrap()).score[Color::White.index()];
+        // d3 is behind c4 so nothing can ever guard it, and e5 covers d4 so it can't catch up
+        let backward = white("4k3/8/8/4p3/2P5/3P4/8/4K3 w - - 0 1");
+        let free = white("4k3/8/8/8/2P5/3P4/8/4K3 w - - 0 1");
+        assert_eq!(Score { mg: backward.mg - free.mg, eg: backward.eg - free.eg }, BACKWARD);
+        // With c2 beside it instead d3 can still be guarded
+        assert_eq!(white("4k3/8/8/4p3/8/3P4/2P5/4K3 w - - 0 1"), white("4k3/8/8/8/8/3P4/2P5/4K3 w - - 0 1"));
+    }
+}
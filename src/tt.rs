@@ -0,0 +1,136 @@
+// Transposition table, remembers what the search found out about positions it has already seen.
+// Every search thread shares the same table without locking: each entry is two atomics, the
+// position's hash xor-ed with the data and the data itself, so an entry torn by two threads
+// writing at once just fails the hash check when it's read back.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Move, Type, MATE};
+
+// Megabytes used when nothing else is asked for
+pub const DEFAULT_MB: usize = 16;
+
+// How the stored score relates to the real one, the search got a cutoff for Lower and Upper
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The real score is at least this
+    Lower,
+    // The real score is at most this
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub depth: i32,
+    pub bound: Bound,
+    pub score: i32,
+    // Best move as given by move_code, 0 when there isn't one
+    pub best: u16,
+}
+
+pub struct TT {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl TT {
+    // Rounds down to a power of two number of entries so the hash can be masked into an index
+    pub fn new(mb: usize) -> TT {
+        let count = mb.max(1)*1024*1024/std::mem::size_of::<[AtomicU64; 2]>();
+        TT { entries: (0..1usize << count.ilog2()).map(|_| [AtomicU64::new(0), AtomicU64::new(0)]).collect() }
+    }
+
+    pub fn clear(&self) {
+        for entry in &self.entries {
+            entry[0].store(0, Ordering::Relaxed);
+            entry[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, hash: u64) -> &[AtomicU64; 2] {
+        &self.entries[hash as usize & (self.entries.len() - 1)]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        let slot = self.slot(hash);
+        let data = slot[1].load(Ordering::Relaxed);
+        if slot[0].load(Ordering::Relaxed) ^ data != hash {
+            return None;
+        }
+        let bound = match data >> 56 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            // Never written
+            _ => return None,
+        };
+        Some(Entry { depth: (data >> 48) as u8 as i32, bound, score: (data >> 16) as u32 as i32, best: data as u16 })
+    }
+
+    // Keeps a deeper result for the same position over a shallower one, anything else gets replaced
+    pub fn store(&self, hash: u64, entry: Entry) {
+        if let Some(old) = self.probe(hash) {
+            if old.depth > entry.depth && entry.bound != Bound::Exact {
+                return;
+            }
+        }
+        let bound = match entry.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let data = entry.best as u64 | (entry.score as u32 as u64) << 16 | (entry.depth.clamp(0, 255) as u64) << 48 | bound << 56;
+        let slot = self.slot(hash);
+        slot[0].store(hash ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+// Squares and promotion of a move packed into 16 bits, from in the low 6 bits, to in the next 6
+pub fn move_code(m: &Move) -> u16 {
+    let promotion = match m.promotion {
+        None => 0,
+        Some(Type::Knight) => 1,
+        Some(Type::Bishop) => 2,
+        Some(Type::Rook) => 3,
+        Some(_) => 4,
+    };
+    ((m.y0*8 + m.x0) | (m.y1*8 + m.x1) << 6 | promotion << 12) as u16
+}
+
+// Mate scores count plies from the root, but a table entry can be reached from a different
+// distance, so they get stored counting from the position itself instead
+pub fn to_tt(score: i32, ply: i32) -> i32 {
+    if score > MATE - 1000 {
+        score + ply
+    }
+    else if score < -MATE + 1000 {
+        score - ply
+    }
+    else {
+        score
+    }
+}
+
+pub fn from_tt(score: i32, ply: i32) -> i32 {
+    if score > MATE - 1000 {
+        score - ply
+    }
+    else if score < -MATE + 1000 {
+        score + ply
+    }
+    else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_rounds_down_to_a_power_of_two() {
+        assert_eq!(TT::new(16).entries.len(), 1 << 20);
+        assert_eq!(TT::new(1).entries.len(), 1 << 16);
+        assert_eq!(TT::new(3).entries.len(), 1 << 17);
+    }
+}
@@ -0,0 +1,272 @@
+// Universal Chess Interface front end, reads commands on stdin and answers on stdout
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::tt::{self, TT};
+use crate::{domove, setup, think, Board, Calculate, Color, Evaluate, Options, Stop, MATE, MAX_DEPTH};
+
+// Depth searched when go doesn't give a depth or any time limit
+const DEFAULT_DEPTH: i32 = 4;
+// Moves the clock is assumed to have to last when go doesn't give movestogo
+const MOVES_LEFT: u64 = 30;
+// Milliseconds kept back from the clock
+const MOVE_OVERHEAD: u64 = 50;
+// Most search threads the Threads option allows
+const MAX_THREADS: usize = 256;
+
+pub fn run() {
+    let mut board = setup();
+    let mut table = Arc::new(TT::new(tt::DEFAULT_MB));
+    let mut options = Options::default();
+    // The search running in the background and how to stop it
+    let mut searching: Option<(Stop, JoinHandle<()>)> = None;
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first().copied() {
+            Some("uci") => {
+                println!("id name chess-engine");
+                println!("id author jpw142");
+                println!("option name Hash type spin default {} min 1 max 65536", tt::DEFAULT_MB);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                for name in ["NullMove", "LateMoveReductions", "ReverseFutility", "Futility", "CheckExtensions"] {
+                    println!("option name {} type check default true", name);
+                }
+                println!("uciok");
+            },
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => {
+                finish(&mut searching);
+                board = setup();
+                table.clear();
+            },
+            Some("setoption") => {
+                finish(&mut searching);
+                setoption(&tokens[1..], &mut table, &mut options);
+            },
+            Some("position") => {
+                finish(&mut searching);
+                match position(&tokens[1..]) {
+                    Ok(b) => board = b,
+                    Err(e) => println!("info string {}", e),
+                }
+            },
+            Some("go") => {
+                finish(&mut searching);
+                searching = Some(go(&board, &table, options, &tokens[1..]));
+            },
+            Some("stop") => finish(&mut searching),
+            // Not part of UCI, shows the current position for debugging
+            Some("d") => {
+                println!("{}", board);
+                println!("Fen: {}", board.to_fen());
+                println!("Key: {:016x}", board.hash);
+            },
+            // Not part of UCI either, explains the evaluation of the current position
+            Some("eval") => println!("{}", board.breakdown()),
+            Some("quit") => break,
+            // Unknown commands are ignored as the protocol asks
+            _ => (),
+        }
+    }
+    finish(&mut searching);
+}
+
+// Stops the background search if there is one and waits for its bestmove
+fn finish(searching: &mut Option<(Stop, JoinHandle<()>)>) {
+    if let Some((stop, handle)) = searching.take() {
+        stop.stop();
+        handle.join().unwrap();
+    }
+}
+
+// setoption name <id> [value <x>]
+fn setoption(tokens: &[&str], table: &mut Arc<TT>, options: &mut Options) {
+    let value_at = tokens.iter().position(|t| *t == "value").unwrap_or(tokens.len());
+    let name = tokens.get(1..value_at).unwrap_or_default().join(" ");
+    let value = tokens.get(value_at + 1..).unwrap_or_default().join(" ");
+    match name.to_lowercase().as_str() {
+        "hash" => match value.parse::<usize>() {
+            Ok(mb) if (1..=65536).contains(&mb) => *table = Arc::new(TT::new(mb)),
+            _ => println!("info string invalid Hash value {}", value),
+        },
+        "threads" => match value.parse::<usize>() {
+            Ok(n) if (1..=MAX_THREADS).contains(&n) => options.threads = n,
+            _ => println!("info string invalid Threads value {}", value),
+        },
+        "nullmove" | "latemovereductions" | "reversefutility" | "futility" | "checkextensions" => {
+            let Ok(on) = value.parse::<bool>() else {
+                println!("info string invalid {} value {}", name, value);
+                return;
+            };
+            match name.to_lowercase().as_str() {
+                "nullmove" => options.null_move = on,
+                "latemovereductions" => options.late_move_reductions = on,
+                "reversefutility" => options.reverse_futility = on,
+                "futility" => options.futility = on,
+                _ => options.check_extensions = on,
+            }
+        },
+        _ => println!("info string unknown option {}", name),
+    }
+}
+
+// position [startpos | fen <fen>] [moves <move>...]
+fn position(tokens: &[&str]) -> Result<Board, String> {
+    let moves_at = tokens.iter().position(|t| *t == "moves").unwrap_or(tokens.len());
+    let mut b = match tokens.first().copied() {
+        Some("startpos") => setup(),
+        Some("fen") => Board::from_fen(&tokens[1..moves_at].join(" ")).map_err(|e| format!("invalid fen: {}", e))?,
+        _ => return Err("position needs startpos or fen".to_string()),
+    };
+    for token in tokens.iter().skip(moves_at + 1) {
+        b.calculate();
+        let m = *b.moves.iter().find(|m| m.to_string() == *token).ok_or(format!("illegal move {}", token))?;
+        b = domove(&b, &m);
+    }
+    Ok(b)
+}
+
+// go [depth <plies>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <moves>] [infinite]
+fn go(board: &Board, table: &Arc<TT>, options: Options, tokens: &[&str]) -> (Stop, JoinHandle<()>) {
+    let mut depth = None;
+    let mut movetime = None;
+    let mut infinite = false;
+    // Time left and increment for white and black
+    let mut time = [None, None];
+    let mut inc = [0, 0];
+    let mut movestogo = None;
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        let mut value = || tokens.next().and_then(|v| v.parse::<u64>().ok());
+        match *token {
+            "depth" => depth = value().map(|d| d.max(1) as i32),
+            "movetime" => movetime = value(),
+            "wtime" => time[0] = value(),
+            "btime" => time[1] = value(),
+            "winc" => inc[0] = value().unwrap_or(0),
+            "binc" => inc[1] = value().unwrap_or(0),
+            "movestogo" => movestogo = value(),
+            "infinite" => infinite = true,
+            _ => (),
+        }
+    }
+
+    let side = if board.c == Color::White {0} else {1};
+    // movetime is exact, a clock gets split into a soft and a hard limit
+    let (soft, hard) = match (movetime, time[side]) {
+        (Some(ms), _) => (Some(ms), Some(ms)),
+        (None, Some(t)) => {
+            let (soft, hard) = limits(t, inc[side], movestogo);
+            (Some(soft), Some(hard))
+        },
+        (None, None) => (None, None),
+    };
+    // Without any limit search the default depth, with one keep deepening until it runs out
+    let depth = depth.unwrap_or(if infinite || hard.is_some() {MAX_DEPTH} else {DEFAULT_DEPTH});
+    let start = Instant::now();
+    let soft = soft.map(|ms| start + Duration::from_millis(ms));
+    let stop = Stop::new(hard.map(|ms| start + Duration::from_millis(ms)));
+
+    let mut b = board.clone();
+    let handle = {
+        let stop = stop.clone();
+        let table = Arc::clone(table);
+        thread::spawn(move || {
+            b.calculate();
+            // Draws are up to the GUI, only no legal moves at all leaves nothing to play
+            if b.moves.is_empty() {
+                println!("bestmove 0000");
+                return;
+            }
+            let m = think(&b, depth, soft, &stop, &table, options, |d, r| {
+                let ms = start.elapsed().as_millis();
+                println!("info depth {} score {} nodes {} nps {} time {} pv {}", d, score(r.score), r.nodes, r.nodes as u128*1000/ms.max(1), ms, r.line());
+            }).best;
+            // An infinite search only reports its move once told to stop
+            while infinite && !stop.stopped() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            println!("bestmove {}", m);
+        })
+    };
+    (stop, handle)
+}
+
+// Soft and hard limits in ms for a move with time ms on the clock and inc ms added after it.
+// The soft limit shares the clock out evenly over the moves left, iterative deepening won't start
+// another iteration after it. The hard limit stops the search outright and allows a few times
+// more for when an iteration is nearly done, but never more than most of what's left.
+fn limits(time: u64, inc: u64, movestogo: Option<u64>) -> (u64, u64) {
+    // Room for sending the move back over slow pipes
+    let left = time.saturating_sub(MOVE_OVERHEAD).max(1);
+    // Never nothing at all, the first iteration gets to finish regardless
+    let soft = (left/movestogo.unwrap_or(MOVES_LEFT).max(1) + inc/2).min(left/2).max(1);
+    let hard = (soft*3).min(left*3/4).max(soft);
+    (soft, hard)
+}
+
+// Score as UCI wants it, in centipawns or in moves until mate
+fn score(value: i32) -> String {
+    let plies = MATE - value.abs();
+    if plies < 1000 {
+        let moves = (plies + 1)/2;
+        format!("mate {}", if value > 0 {moves} else {-moves})
+    }
+    else {
+        format!("cp {}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_plays_the_moves() {
+        let b = position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"]).unwrap();
+        assert_eq!(b.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        let b = position(&["fen", "4k3/8/8/8/8/8/4P3/4K3", "w", "-", "-", "0", "1", "moves", "e2e4"]).unwrap();
+        assert_eq!(b.to_fen(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+        // Without moves
+        assert_eq!(position(&["startpos"]).unwrap().to_fen(), setup().to_fen());
+    }
+
+    #[test]
+    fn position_rejects_bad_input() {
+        assert_eq!(position(&["startpos", "moves", "e2e4", "e2e4"]).err(), Some("illegal move e2e4".to_string()));
+        assert_eq!(position(&["startpos", "moves", "e2e5"]).err(), Some("illegal move e2e5".to_string()));
+        assert_eq!(position(&["fen", "8/8/8/8/8/8/8/8", "w", "-", "-"]).err(), Some("invalid fen: white must have exactly one king".to_string()));
+        assert!(position(&[]).is_err());
+    }
+
+    #[test]
+    fn limits_share_out_the_clock() {
+        // A minute with 50 ms kept back, spread over 30 moves and three times that at most
+        assert_eq!(limits(60000, 0, None), (1998, 5994));
+        // Half the increment on top
+        assert_eq!(limits(60000, 1000, None), (2498, 7494));
+        // The last move before the time control can use half the clock, and the hard limit stops at three quarters
+        assert_eq!(limits(60000, 0, Some(1)), (29975, 44962));
+        assert_eq!(limits(60000, 0, Some(0)), (29975, 44962));
+        // Less on the clock than the overhead
+        assert_eq!(limits(30, 0, None), (1, 1));
+        assert_eq!(limits(30, 1000, None), (1, 1));
+        assert_eq!(limits(53, 0, None), (1, 2));
+    }
+
+    #[test]
+    fn scores_are_centipawns_or_moves_to_mate() {
+        assert_eq!(score(35), "cp 35");
+        assert_eq!(score(-20), "cp -20");
+        assert_eq!(score(MATE - 1), "mate 1");
+        assert_eq!(score(MATE - 3), "mate 2");
+        assert_eq!(score(-MATE + 2), "mate -1");
+        assert_eq!(score(-MATE + 4), "mate -2");
+    }
+}
@@ -0,0 +1,60 @@
+// Random keys for Zobrist hashing, a position's hash is the xor of the keys of everything in it
+// so moving a piece or flipping the side to move only takes a couple of xors to update
+
+pub struct Keys {
+    // Indexed by Color::index, Type as usize and square
+    pub pieces: [[[u64; 64]; 6]; 2],
+    // Only xor-ed in when black is to move
+    pub side: u64,
+    // Indexed by all four castling right bits at once
+    pub castling: [u64; 16],
+    // Indexed by the file of the en passant square
+    pub ep: [u64; 8],
+}
+
+// A static so there is one copy in memory rather than one wherever it gets used
+pub static KEYS: Keys = keys();
+
+// Same xorshift as the magic search, worked out at compile time with a fixed seed
+// so hashes stay the same from run to run
+const fn keys() -> Keys {
+    let mut seed: u64 = 0x1d8e4e27c47d124f;
+    let mut keys = Keys { pieces: [[[0; 64]; 6]; 2], side: 0, castling: [0; 16], ep: [0; 8] };
+    let mut c = 0;
+    while c < 2 {
+        let mut p = 0;
+        while p < 6 {
+            let mut sq = 0;
+            while sq < 64 {
+                seed = next(seed);
+                keys.pieces[c][p][sq] = seed.wrapping_mul(0x2545f4914f6cdd1d);
+                sq += 1;
+            }
+            p += 1;
+        }
+        c += 1;
+    }
+    seed = next(seed);
+    keys.side = seed.wrapping_mul(0x2545f4914f6cdd1d);
+    // No castling rights keeps a zero key so it drops out of the hash
+    let mut i = 1;
+    while i < 16 {
+        seed = next(seed);
+        keys.castling[i] = seed.wrapping_mul(0x2545f4914f6cdd1d);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 8 {
+        seed = next(seed);
+        keys.ep[i] = seed.wrapping_mul(0x2545f4914f6cdd1d);
+        i += 1;
+    }
+    keys
+}
+
+const fn next(mut seed: u64) -> u64 {
+    seed ^= seed >> 12;
+    seed ^= seed << 25;
+    seed ^= seed >> 27;
+    seed
+}